@@ -0,0 +1,43 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use shared::master::{self, ServerListing};
+
+use crate::config::Config;
+
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+///The servers the master listed, filled in from a thread once it answers
+#[derive(Default)]
+pub struct ServerBrowser {
+    pub servers: Arc<Mutex<Option<Vec<ServerListing>>>>,
+}
+
+pub fn build(app: &mut App) {
+    app.init_resource::<ServerBrowser>()
+        .add_startup_system_to_stage(StartupStage::PostStartup, setup_browser);
+}
+
+fn setup_browser(browser: Res<ServerBrowser>, config: Res<Config>) {
+    let master = match config.master.clone() {
+        Some(m) => m,
+        None => return,
+    };
+
+    let servers = browser.servers.clone();
+    std::thread::spawn(move || match master::query(&master, QUERY_TIMEOUT) {
+        Ok(list) => {
+            info!("{} lists {} servers", master, list.len());
+            for server in &list {
+                info!(
+                    "  {} on {}: {} players, {}",
+                    server.info.name, server.addr, server.info.players, server.info.map
+                );
+            }
+            *servers.lock().unwrap() = Some(list);
+        }
+        Err(e) => warn!("couldn't get the server list from {}: {}", master, e),
+    });
+}
@@ -1,6 +1,9 @@
 use bevy::prelude::*;
 
 use serde::Deserialize;
+use shared::jump::{JumpProfile, JumpProfiles};
+use shared::net::Credentials;
+use shared::{MovementProfiles, PhysicsProperties};
 use std::path::Path;
 
 #[derive(Deserialize)]
@@ -9,6 +12,15 @@ pub enum NetMode {
     Client,
 }
 
+///How the client talks to the server
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransportKind {
+    ///Framed tcp, with udp for snapshots
+    Tcp,
+    ///A single websocket, what browsers can use
+    WebSocket,
+}
+
 ///The user keybinds and other personal settings
 #[derive(Deserialize)]
 pub struct Config {
@@ -23,11 +35,75 @@ pub struct Config {
     pub dash: KeyCode,
 
     pub net_mode: Option<NetMode>,
+    ///Tcp if unset, browsers always use websockets
+    pub transport: Option<TransportKind>,
+    ///`host:port` of the server. If unset it's localhost on the transport's default port, or
+    ///the host the page came from in a browser.
+    pub server: Option<String>,
+    ///`host:port` of a master server to fetch the server list from
+    pub master: Option<String>,
+    ///Room to join on the server, the server's default room if unset
+    pub room: Option<String>,
+    ///Log in with a name and the secret the server has for it, if the server requires it
+    pub name: Option<String>,
+    pub secret: Option<String>,
+    ///A session token the server issued, used instead of the name and secret. The one from the
+    ///last login is saved and used by itself if this is unset.
+    pub token: Option<String>,
+    ///Encrypt everything sent to the server. Browsers rely on wss instead.
+    #[serde(default)]
+    pub encrypt: bool,
+    ///The server's public key, logged when it starts. Without it the server's word is taken for
+    ///its key, which keeps out eavesdroppers but not someone in between.
+    pub server_key: Option<String>,
+
+    ///Join the room as a spectator instead of with a player
+    #[serde(default)]
+    pub spectate: bool,
 }
 
 const DEFAULT_CONFIG: &str = include_str!("../assets/default_config.yaml");
+const DEFAULT_JUMP_PROFILES: &str = include_str!("../assets/jump_profiles.yaml");
+const DEFAULT_MOVEMENT_PROFILES: &str = include_str!("../assets/movement_profiles.yaml");
+const JUMP_PROFILES_FILE: &str = "./jump_profiles.yaml";
+const MOVEMENT_PROFILES_FILE: &str = "./movement_profiles.yaml";
+///The session token from the last login and the server it is for, a file next to the game or
+///a local storage key in browsers
+const SESSION: &str = "session.txt";
 
 impl Config {
+    pub fn credentials(&self) -> Credentials {
+        let token = self.token.clone().or_else(|| self.saved_session());
+        match (token, &self.name, &self.secret) {
+            (Some(token), _, _) => Credentials::Token(token),
+            (None, Some(name), Some(secret)) => Credentials::Secret {
+                name: name.clone(),
+                secret: secret.clone(),
+            },
+            _ => Credentials::Anonymous,
+        }
+    }
+
+    ///Saved sessions are only used with the server that issued them
+    fn server_key(&self) -> &str {
+        self.server.as_deref().unwrap_or_default()
+    }
+
+    fn saved_session(&self) -> Option<String> {
+        let saved = read_stored(SESSION)?;
+        let (server, token) = saved.trim().split_once(' ')?;
+        (server == self.server_key()).then(|| token.to_string())
+    }
+
+    ///Keeps the session token for the next start, or forgets it if it's `None`
+    pub fn save_session(&self, token: Option<&str>) {
+        write_stored(
+            SESSION,
+            token.map(|t| format!("{} {}", self.server_key(), t)),
+        );
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
     pub fn load_or_create<P: AsRef<Path>>(file: &P) -> Self {
         let config = std::fs::read_to_string(file).unwrap_or_else(move |_| {
             let mut f = std::fs::File::create(file).expect("couldn't open new config for writing");
@@ -44,10 +120,99 @@ impl Config {
         serde_yaml::from_str(&config).expect("Couldn't read config")
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
     pub fn load_or_create_default() -> Self {
         let file = "./config.yaml";
         Config::load_or_create(&file)
     }
+
+    ///Browsers have no filesystem, the config lives in local storage instead
+    #[cfg(target_arch = "wasm32")]
+    pub fn load_or_create_default() -> Self {
+        const KEY: &str = "config";
+
+        let storage = local_storage();
+        let config = match storage.as_ref().map(|s| s.get_item(KEY)) {
+            Some(Ok(Some(config))) => config,
+            _ => {
+                if let Some(storage) = storage {
+                    storage
+                        .set_item(KEY, DEFAULT_CONFIG)
+                        .expect("Couldn't write new config ??");
+                }
+                DEFAULT_CONFIG.into()
+            }
+        };
+
+        let mut config = Config::load_from_string(&config);
+        config.apply_link_params();
+        config
+    }
+
+    ///Lets a session be shared as a link, `?server=host:port&room=name&spectate`
+    #[cfg(target_arch = "wasm32")]
+    fn apply_link_params(&mut self) {
+        let search = match web_sys::window().and_then(|w| w.location().search().ok()) {
+            Some(s) => s,
+            None => return,
+        };
+        let params = match web_sys::UrlSearchParams::new_with_str(&search) {
+            Ok(p) => p,
+            Err(_) => return,
+        };
+
+        if let Some(server) = params.get("server") {
+            self.server = Some(server);
+        }
+        if let Some(room) = params.get("room") {
+            self.room = Some(room);
+        }
+        if params.has("spectate") {
+            self.spectate = true;
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_stored(name: &str) -> Option<String> {
+    std::fs::read_to_string(name).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_stored(name: &str, contents: Option<String>) {
+    let result = match contents {
+        Some(contents) => std::fs::write(name, contents),
+        None if Path::new(name).exists() => std::fs::remove_file(name),
+        None => Ok(()),
+    };
+    if let Err(e) = result {
+        warn!("Couldn't save {}: {}", name, e);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window().and_then(|w| w.local_storage().ok().flatten())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_stored(name: &str) -> Option<String> {
+    local_storage()?.get_item(name).ok().flatten()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_stored(name: &str, contents: Option<String>) {
+    let storage = match local_storage() {
+        Some(s) => s,
+        None => return,
+    };
+    let result = match contents {
+        Some(contents) => storage.set_item(name, &contents),
+        None => storage.remove_item(name),
+    };
+    if result.is_err() {
+        warn!("Couldn't save {} to local storage", name);
+    }
 }
 
 impl Default for Config {
@@ -61,6 +226,90 @@ fn default_config_valid() {
     Config::default();
 }
 
+///Reads a profiles file from next to the game if there is one, so it can be changed without
+///rebuilding, otherwise the built in `default`
+fn load_profiles<T>(
+    file: &str,
+    default: &str,
+    from_yaml: fn(&str) -> Result<T, serde_yaml::Error>,
+) -> T {
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Ok(profiles) = std::fs::read_to_string(file) {
+        match from_yaml(&profiles) {
+            Ok(profiles) => return profiles,
+            Err(e) => warn!("Couldn't read {}, using the defaults: {}", file, e),
+        }
+    }
+
+    from_yaml(default).unwrap_or_else(|e| panic!("Couldn't read default {}: {}", file, e))
+}
+
+///Gravity and jump tuning
+pub fn load_jump_profiles() -> JumpProfiles {
+    load_profiles(
+        JUMP_PROFILES_FILE,
+        DEFAULT_JUMP_PROFILES,
+        JumpProfiles::from_yaml,
+    )
+}
+
+///Speeds, friction and dashing
+pub fn load_movement_profiles() -> MovementProfiles {
+    load_profiles(
+        MOVEMENT_PROFILES_FILE,
+        DEFAULT_MOVEMENT_PROFILES,
+        MovementProfiles::from_yaml,
+    )
+}
+
+#[test]
+fn default_jump_profiles_valid() {
+    JumpProfiles::from_yaml(DEFAULT_JUMP_PROFILES).unwrap();
+    let movement = MovementProfiles::from_yaml(DEFAULT_MOVEMENT_PROFILES).unwrap();
+    assert!(movement.0.contains_key("player"));
+}
+
+///Which entry of the profile files an entity moves by, it's updated when the files change
+#[derive(Component)]
+pub struct Profile(pub String);
+
+///When the profile files last changed, checked every second
+#[cfg(not(target_arch = "wasm32"))]
+struct ProfileWatch {
+    timer: Timer,
+    modified: [Option<std::time::SystemTime>; 2],
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn profiles_modified() -> [Option<std::time::SystemTime>; 2] {
+    [JUMP_PROFILES_FILE, MOVEMENT_PROFILES_FILE]
+        .map(|file| std::fs::metadata(file).and_then(|m| m.modified()).ok())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn system_reload_profiles(
+    time: Res<Time>,
+    mut watch: ResMut<ProfileWatch>,
+    mut profiles: Query<(&Profile, &mut PhysicsProperties, &mut JumpProfile)>,
+) {
+    if !watch.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let modified = profiles_modified();
+    if modified == watch.modified {
+        return;
+    }
+    watch.modified = modified;
+
+    let movement = load_movement_profiles();
+    let jump = load_jump_profiles();
+    for (profile, mut props, mut jump_profile) in profiles.iter_mut() {
+        *props = movement.get(&profile.0);
+        *jump_profile = jump.get(&profile.0);
+    }
+    info!("Reloaded the movement and jump profiles");
+}
+
 fn setup_read_config(mut config: ResMut<Config>) {
     *config = Config::load_or_create_default();
 }
@@ -68,4 +317,11 @@ fn setup_read_config(mut config: ResMut<Config>) {
 pub fn build(app: &mut App) {
     app.init_resource::<Config>()
         .add_startup_system(setup_read_config);
+
+    #[cfg(not(target_arch = "wasm32"))]
+    app.insert_resource(ProfileWatch {
+        timer: Timer::from_seconds(1.0, true),
+        modified: profiles_modified(),
+    })
+    .add_system(system_reload_profiles);
 }
@@ -1,38 +1,30 @@
 use bevy::prelude::*;
-use rand::{thread_rng, Rng};
 
-#[derive(Component)]
-pub struct Enemy {
-    pub facing: f32,
-    pub facing_vel: f32,
-}
+use shared::enemy::{self, Enemy};
+use shared::knockback::Knockback;
+use shared::replication::NetId;
 
-impl Default for Enemy {
-    fn default() -> Self {
-        Self {
-            facing: 0.0,
-            facing_vel: 0.0,
-        }
-    }
-}
+use crate::networking::ServerConnection;
 
 //use crate::{config::Config, input::InputEvent};
 
 fn update(
     mut commands: Commands,
     time: Res<Time>,
+    connection: Res<ServerConnection>,
 
     mut spawn_timer: ResMut<EnemySpawnTimer>,
 
     //mut input_events: EventReader<InputEvent>,
     //meshes: Res<Assets<Mesh>>,
     //mut materials: ResMut<Assets<StandardMaterial>>,
-    mut enemy_query: Query<(Entity, &mut Enemy, &mut Transform)>,
+    mut enemy_query: Query<(&mut Enemy, &mut Knockback, &mut Transform), Without<NetId>>,
     assets_server: Res<AssetServer>,
 ) {
     spawn_timer.0.tick(time.delta());
 
-    if spawn_timer.0.finished() {
+    //the server owns the enemies once we are connected
+    if spawn_timer.0.finished() && !connection.is_connected() {
         let mesh = assets_server.load("cube.gltf#Mesh0/Primitive0");
 
         commands
@@ -45,17 +37,13 @@ fn update(
                 //material: player_material.clone(),
                 ..Default::default()
             })
-            .insert(Enemy::default());
+            .insert(Enemy::default())
+            .insert(Knockback::default());
     }
 
-    for (_, mut enemy, mut trans) in enemy_query.iter_mut() {
-        trans.rotation = Quat::from_rotation_y(enemy.facing);
-        enemy.facing += 1.0 * time.delta_seconds() * enemy.facing_vel;
-        enemy.facing_vel += 1.0 * time.delta_seconds() * thread_rng().gen_range(-1.0..1.0);
-
-        let (x, z) = enemy.facing.sin_cos();
-
-        trans.translation += Vec3::new(x, 0.0, z) * time.delta_seconds() * 20.0;
+    for (mut enemy, mut knockback, mut trans) in enemy_query.iter_mut() {
+        enemy::knocked_back(&mut enemy, &mut knockback, &mut trans, time.delta_seconds());
+        enemy::wander(&mut enemy, &mut trans, time.delta_seconds());
     }
 }
 
@@ -1,29 +1,63 @@
+use bevy::ecs::schedule::ShouldRun;
+use bevy::ecs::system::SystemParam;
 use bevy::{input::mouse::MouseMotion, input::mouse::MouseWheel, prelude::*};
 //use bevy_egui::{egui, EguiContext, EguiPlugin};
 
+#[cfg(not(target_arch = "wasm32"))]
+mod browser;
 mod camera;
 mod config;
 mod enemy;
 mod input;
 mod networking;
 mod projectile;
+mod spectator;
 mod ui;
 mod utils;
 
-use shared::{Physics, PhysicsProperties};
-use utils::RotatableVector;
+use shared::collision::{Collider, CollisionShape, Grounded};
+use shared::knockback::Knockback;
+use shared::movement::{
+    self, BodyQuery, DashEnded, DashStarted, MovementCharges, MovementInput, MovementState,
+    MovementStateChanged, PhysicsPosition,
+};
+use shared::Physics;
+use utils::{ramp_mesh, RotatableVector};
 
 fn main() {
     let mut app = App::new();
 
-    app.insert_resource(Msaa { samples: 8 })
+    //the browser build draws into the canvas of index.html
+    #[cfg(target_arch = "wasm32")]
+    app.insert_resource(WindowDescriptor {
+        canvas: Some("#bevy".into()),
+        fit_canvas_to_parent: true,
+        ..Default::default()
+    });
+
+    //webgl2 can't do more than 4 samples
+    let samples = if cfg!(target_arch = "wasm32") { 4 } else { 8 };
+
+    app.insert_resource(Msaa { samples })
         .add_plugins(DefaultPlugins)
         //.add_plugin(EguiPlugin)
         .init_resource::<MouseInputState>()
+        .init_resource::<movement::FixedTimestep>()
+        .add_event::<DashStarted>()
+        .add_event::<DashEnded>()
+        .add_event::<MovementStateChanged>()
         .add_startup_system(setup_scene)
         .add_startup_system(setup_window)
         .add_system(system_update_player_cam)
-        .add_system(system_update_movement)
+        //after input is read in PreUpdate, before anything uses the player's position
+        .add_stage_before(
+            CoreStage::Update,
+            "movement",
+            SystemStage::single_threaded()
+                .with_run_criteria(run_movement_steps)
+                .with_system(system_update_movement),
+        )
+        .add_system(system_interpolate_movement)
         .add_system(system_window)
         .add_system(system_mouse);
 
@@ -33,6 +67,10 @@ fn main() {
     projectile::build(&mut app);
     input::build(&mut app);
     enemy::build(&mut app);
+    spectator::build(&mut app);
+    //browsers can't send udp, the page would have to ask the master over http
+    #[cfg(not(target_arch = "wasm32"))]
+    browser::build(&mut app);
 
     app.run();
 }
@@ -113,24 +151,18 @@ fn setup_scene(
                 attached_entity: Some(e),
                 ..Default::default()
             },
-            PhysicsProperties {
-                movement_speed_ground: 15.0,
-                movement_speed_air: 1.0,
-                movement_acceleration: 15.0 * 10.0,
-                dash_cooldown: 0.5,
-            },
+            config::load_movement_profiles().get("player"),
             Physics {
-                gravity_func: |_x, _launchvel| {
-                    //let offset = 25.0;
-                    //35f32.min((x - 0.5).powf(2.0) + offset)
-
-                    30.0
-
-                    //35f32.min(15. * x)
-                },
                 last_jump: -100.0,
                 ..Default::default()
             },
+            config::load_jump_profiles().get("player"),
+            config::Profile("player".into()),
+            PhysicsPosition::new(Vec3::ZERO),
+            CollisionShape::default(),
+            MovementCharges::default(),
+            MovementState::default(),
+            Knockback::default(),
         ))
         .id();
 
@@ -161,27 +193,61 @@ fn setup_scene(
 
     let cubes = [(5.0, 1.0, 5.0), (25.0, 1.0, 45.0), (-20.0, 1.0, 0.0)];
     for cube in &cubes {
-        commands.spawn_bundle(PbrBundle {
-            mesh: meshes.add(Mesh::from(shape::Cube { size: 1.0 })),
-            material: materials.add(Color::rgb(0.5, 0.5, 0.5).into()),
+        commands
+            .spawn_bundle(PbrBundle {
+                mesh: meshes.add(Mesh::from(shape::Cube { size: 1.0 })),
+                material: materials.add(Color::rgb(0.5, 0.5, 0.5).into()),
+                transform: Transform {
+                    translation: Vec3::new(cube.0, cube.1, cube.2),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(Collider::cube(1.0));
+    }
+
+    //banana
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Cube { size: 0.2 })),
+            material: materials.add(Color::rgb(1.0, 0.92, 0.21).into()),
             transform: Transform {
-                translation: Vec3::new(cube.0, cube.1, cube.2),
+                translation: Vec3::new(-5.0, 1.0, 0.0),
                 ..Default::default()
             },
             ..Default::default()
+        })
+        .insert(Collider::cube(0.2));
+
+    //a platform, and a ramp up to it
+    let platform = Vec3::new(6.0, 1.0, 3.0);
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Box::new(
+                platform.x * 2.0,
+                platform.y * 2.0,
+                platform.z * 2.0,
+            ))),
+            material: materials.add(Color::rgb(0.5, 0.5, 0.5).into()),
+            transform: Transform::from_xyz(0.0, 1.0, -20.0),
+            ..Default::default()
+        })
+        .insert(Collider::Cuboid {
+            half_extents: platform,
         });
-    }
 
-    //banana
-    commands.spawn_bundle(PbrBundle {
-        mesh: meshes.add(Mesh::from(shape::Cube { size: 0.2 })),
-        material: materials.add(Color::rgb(1.0, 0.92, 0.21).into()),
-        transform: Transform {
-            translation: Vec3::new(-5.0, 1.0, 0.0),
+    let ramp = Collider::Ramp {
+        half_extents: Vec3::new(4.0, 1.0, 3.0),
+        rises_towards: Vec2::X,
+    };
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(ramp_mesh(ramp.half_extents(), Vec2::X)),
+            material: materials.add(Color::rgb(0.4, 0.4, 0.45).into()),
+            transform: Transform::from_xyz(-10.0, 1.0, -20.0),
             ..Default::default()
-        },
-        ..Default::default()
-    });
+        })
+        .insert(ramp);
 }
 
 fn setup_window(mut windows: ResMut<Windows>) {
@@ -255,24 +321,37 @@ fn system_mouse(
     camera.distance = camera.distance.max(5.).min(100.);
 }
 
+///Runs the movement stage once for every fixed step this frame adds up to
+fn run_movement_steps(time: Res<Time>, mut clock: ResMut<movement::FixedTimestep>) -> ShouldRun {
+    if clock.next_step(time.delta()) {
+        ShouldRun::YesAndCheckAgain
+    } else {
+        ShouldRun::No
+    }
+}
+
+#[derive(SystemParam)]
+struct MovementEvents<'w, 's> {
+    dash_started: EventWriter<'w, 's, DashStarted>,
+    dash_ended: EventWriter<'w, 's, DashEnded>,
+    state_changed: EventWriter<'w, 's, MovementStateChanged>,
+}
+
 fn system_update_movement(
-    time: Res<Time>,
+    clock: Res<movement::FixedTimestep>,
     keyboard_input: Res<Input<KeyCode>>,
-    //mut ui_debug: ResMut<ui::UIDebugInfo>,
     config: Res<config::Config>,
-    mut player_query: Query<(
-        &CameraOrientation,
-        &mut Transform,
-        &mut Physics,
-        &PhysicsProperties,
-    )>,
+    mut commands: Commands,
+    mut player_query: Query<(Entity, &CameraOrientation, BodyQuery)>,
+    colliders: Query<(Entity, &Transform, &Collider)>,
+    mut events: MovementEvents,
 ) {
     let has_player = match player_query.iter_mut().next() {
         Some(p) => p,
         None => return,
     };
 
-    let (player_cam, mut player_transform, mut phys, phys_prop) = has_player;
+    let (player, player_cam, mut body) = has_player;
 
     let mut movement2d_direction = Vec2::ZERO;
     let [m_up, m_left, m_down, m_right] = config.movement;
@@ -293,94 +372,58 @@ fn system_update_movement(
         movement2d_direction = movement2d_direction.normalize();
     }
 
-    use utils::Vec2toVec3;
-
-    let movement2d_direction =
-        movement2d_direction.rotate_ang(player_cam.yaw - 90.0f32.to_radians());
-
-    let is_in_air = if player_transform.translation.y <= 0.0 {
-        false
-    } else {
-        true
-    };
-
-    let mut movement2d = movement2d_direction.clone();
-    movement2d *= if is_in_air {
-        phys_prop.movement_speed_air
-    } else {
-        phys_prop.movement_speed_ground
+    let to_world = player_cam.yaw - 90.0f32.to_radians();
+    let input = MovementInput {
+        direction: movement2d_direction.rotate_ang(to_world),
+        facing: Vec2::Y.rotate_ang(to_world),
+        jump: keyboard_input.pressed(config.jump),
+        dash: keyboard_input.pressed(config.dash),
     };
-    movement2d *= phys_prop.movement_acceleration / phys_prop.movement_speed_ground;
-
-    let delta_y_vel =
-        (phys.gravity_func)((time.seconds_since_startup() - phys.last_jump) as f32, 5.0);
-    let delta_y_vel = delta_y_vel * time.delta_seconds();
 
-    phys.velocity -= Vec3::new(0.0, delta_y_vel, 0.0);
-
-    //walking section
-    if !is_in_air {
-        //slow the player when on ground
-        let dynamic_friction = 5.0;
-        let static_friction = 15.0;
-        let mut friction_vel = phys.walking_velocity * -dynamic_friction * time.delta_seconds();
-
-        if phys.walking_velocity.length() < 0.1 {
-            //For low velocities, just stop the player
-            friction_vel = phys.walking_velocity * -1.0;
-        } else {
-            friction_vel +=
-                phys.walking_velocity.normalize() * -static_friction * time.delta_seconds();
-        };
-        phys.walking_velocity += friction_vel;
-    }
-    phys.walking_velocity += movement2d * time.delta_seconds();
-
-    if phys.walking_velocity.length() > phys_prop.movement_speed_ground {
-        phys.walking_velocity = phys.walking_velocity.normalize() * phys_prop.movement_speed_ground;
+    let solids: Vec<_> = colliders
+        .iter()
+        .map(|(entity, transform, collider)| collider.solid(Some(entity), transform.translation))
+        .collect();
+
+    let outcome = movement::step(
+        body.body(),
+        &solids,
+        &input,
+        clock.elapsed(),
+        movement::TIMESTEP.as_secs_f32(),
+    );
+
+    if let Some(direction) = outcome.dash_started {
+        events.dash_started.send(DashStarted {
+            entity: player,
+            direction,
+            invulnerable_until: body.phys.invulnerable_until,
+        });
     }
-
-    //dashing section
-    if keyboard_input.pressed(config.dash)
-        && phys.last_dash < time.seconds_since_startup() - phys_prop.dash_cooldown
-    {
-        phys.last_dash = time.seconds_since_startup();
-        //phys.walking_velocity = Vec2::zero();
+    if outcome.dash_ended {
+        events.dash_ended.send(DashEnded { entity: player });
     }
-
-    fn dash_falloff_func(time: f32) -> f32 {
-        if time > 1.0 {
-            0.0
-        } else if time > 0.9 {
-            (-time + 1.0) * 0.4
-        } else if time > 0.5 {
-            (-time + 0.9).powf(0.5) * 0.4
-        } else {
-            1.0
-        }
+    if let Some((from, to)) = outcome.state_changed {
+        events.state_changed.send(MovementStateChanged {
+            entity: player,
+            from,
+            to,
+        });
     }
 
-    let dash_time = time.seconds_since_startup() - phys.last_dash;
-    let dash_percent = 3.0 * dash_time;
-
-    phys.dash_velocity = movement2d_direction.xz3() * dash_falloff_func(dash_percent as f32) * 50.0;
-
-    //ui_debug.speed = phys.walking_velocity.length() + phys.dash_velocity.length();
-    //ui_debug.updates += 1;
-    //ui_debug.fr = 1.0 / time.delta_seconds_f64();
-
-    player_transform.translation +=
-        (phys.velocity + phys.walking_velocity.xz3() + phys.dash_velocity) * time.delta_seconds();
-
-    if !is_in_air {
-        player_transform.translation.y = 0.0;
-        phys.velocity.y = 0.0;
+    match outcome.ground {
+        Some(ground) => commands.entity(player).insert(ground),
+        None => commands.entity(player).remove::<Grounded>(),
+    };
+}
 
-        if keyboard_input.pressed(config.jump) {
-            phys.velocity.y = 15.0;
-            player_transform.translation.y = 0.0 + f32::EPSILON;
-            phys.last_jump = time.seconds_since_startup();
-        }
+///Draws everything that moves in fixed steps between its last two steps
+fn system_interpolate_movement(
+    clock: Res<movement::FixedTimestep>,
+    mut query: Query<(&PhysicsPosition, &mut Transform), With<Physics>>,
+) {
+    for (position, mut transform) in query.iter_mut() {
+        transform.translation = position.interpolate(clock.alpha());
     }
 }
 
@@ -1,14 +1,24 @@
 #![allow(dead_code, unused_variables, unused_mut)]
 use bevy::prelude::*;
-use message_io::{
-    network::{NetEvent, Transport},
-    node::{self, NodeEvent},
-};
-use std::thread;
-use std::{io::Read, net::TcpStream, sync::Arc, sync::Mutex, time::Duration};
+use bevy::utils::HashMap;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
 
+use shared::enemy::Enemy;
+use shared::knockback::{ApplyImpulse, Impulse, Knockback};
+use shared::net::{ClientId, RejectReason};
+use shared::replication::{default_registry, NetId, ReplicationMessage, ReplicationRegistry};
+use shared::snapshot::{WorldState, MAX_BASELINE_AGE};
 use shared::NetworkingAction;
 
+use crate::config::Config;
+use crate::spectator::Spectator;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native;
+#[cfg(target_arch = "wasm32")]
+mod web;
+
 type NetworkQueue = Arc<Mutex<Vec<NetworkingAction>>>;
 
 #[derive(Default)]
@@ -18,106 +28,57 @@ struct NetworkingQueues {
     outgoing: NetworkQueue,
 }
 
-#[derive(Component)]
-struct NetworkEnt;
-
-fn setup_networking(
-    mut commands: Commands,
-    mut netqueues: ResMut<NetworkingQueues>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    config: Res<crate::config::Config>,
-    assets_server: Res<AssetServer>,
-) {
-    let (inc, out) = (netqueues.incoming.clone(), netqueues.outgoing.clone());
-    let jh = thread::spawn(move || start_player(inc, out));
-    netqueues.setup = true;
+///What the server told us about ourselves
+#[derive(Default)]
+pub struct ServerConnection {
+    pub client_id: Option<ClientId>,
+    pub room: Option<String>,
+    pub player: Option<NetId>,
+    ///Token to log in with instead of our secret, on servers with authentication
+    pub session: Option<String>,
+    ///Pushes the server gave our player, waiting to be applied to it
+    impulses: Vec<Impulse>,
 }
 
-fn handle_incoming(mut person: TcpStream, inc: NetworkQueue) {
-    loop {
-        let mut buf = [0; 10000];
-        let read = person.read(&mut buf).unwrap();
-
-        if read == 0 {
-            thread::sleep(Duration::new(0, 100_000));
-            continue;
-        }
-
-        let msg = String::from_utf8_lossy(&buf[..read]);
-
-        for json in msg.split("\n") {
-            if json.len() == 0 {
-                break;
-            }
-
-            info!("{}", &json);
-
-            let k: NetworkingAction = serde_json::from_str(&json).unwrap();
-
-            inc.lock().unwrap().push(k);
-        }
+impl ServerConnection {
+    pub fn is_connected(&self) -> bool {
+        self.client_id.is_some()
     }
 }
 
-fn handle_outgoing(mut stream: TcpStream, out: NetworkQueue) {
-    loop {}
-}
-
-fn start_player(inc: NetworkQueue, out: NetworkQueue) {
-    info!("starting player");
-    let (handler, listener) = node::split::<()>();
-
-    let (server, _) = match handler.network().connect(Transport::Tcp, "127.0.0.1:7777") {
-        Ok(d) => d,
-        Err(_) => return info!("failed to connect to active server",),
-    };
-
-    info!("probably connected");
-
-    handler.network().send(server, "hello".as_bytes());
-    let h2 = handler.clone();
-
-    let mut i = 0;
-    std::thread::spawn(move || {
-        loop {
-            i += 1;
-            h2.network().send(
-                server,
-                &serde_json::to_vec(&NetworkingAction::Heartbeat).unwrap(),
-            );
+///Sent to the server by other systems, dropped while we aren't connected
+pub struct ToServer(pub NetworkingAction);
 
-            //empty the outs queue because we're using it now
-            let outs = std::mem::replace(&mut *out.lock().unwrap(), Vec::new());
-
-            for action in outs {
-                let message = serde_json::to_vec(&action).unwrap();
+///Replication messages waiting to be applied to the world
+#[derive(Default)]
+struct IncomingReplication {
+    messages: Vec<ReplicationMessage>,
+    ///Set when we switch rooms, everything from the old room has to go
+    reset: bool,
+    ///Of the room we are in, snapshots with any other are from a room we left
+    epoch: Option<u64>,
+}
 
-                h2.network().send(server, &*message);
-            }
+///Snapshots we rebuilt, newest last. Deltas from the server refer to one of these.
+#[derive(Default)]
+struct SnapshotHistory {
+    latest: u64,
+    received: VecDeque<(u64, WorldState)>,
+    ///The state the local copies currently match
+    applied: WorldState,
+}
 
-            std::thread::sleep(Duration::from_millis((1000.0f32 / 128.0).floor() as u64));
-        }
-    });
+///Maps server entities to their local copies
+#[derive(Default)]
+pub struct NetEntityMap(pub HashMap<NetId, Entity>);
 
-    listener.for_each(move |event| {
-        match event {
-            NodeEvent::Signal(_s) => {
-                info!("signal...");
-            }
-            NodeEvent::Network(net_event) => match net_event {
-                NetEvent::Message(endpoint, _data) => {
-                    //i += 1;
-                    //handler.network().send(server, &['b' as u8; 1200]);
-                    //println!("got some data", );
-                }
-                NetEvent::Disconnected(_) => {
-                    info!("disconnected from server",);
-                }
-                _ => {}
-            },
-        }
-    });
+///Queued before anything else, the server ignores us until it has seen it
+fn setup_hello(netqueues: Res<NetworkingQueues>, config: Res<Config>) {
+    netqueues
+        .outgoing
+        .lock()
+        .unwrap()
+        .insert(0, NetworkingAction::Hello(config.credentials()));
 }
 
 struct NetworkingTimer(Timer);
@@ -125,16 +86,91 @@ struct NetworkingTimer(Timer);
 fn system_update_networking(
     mut nets: ResMut<NetworkingQueues>,
     mut timer: ResMut<NetworkingTimer>,
+    mut connection: ResMut<ServerConnection>,
+    mut replication: ResMut<IncomingReplication>,
     time: Res<Time>,
     config: Res<crate::config::Config>,
-    mut player_query: ParamSet<(
-        Query<(&crate::CameraOrientation, &Transform)>,
-        Query<(&NetworkEnt, &mut Transform)>,
-    )>,
+    player_query: Query<(&crate::CameraOrientation, &Transform), Without<Spectator>>,
 ) {
     if !nets.setup {
         return;
     }
+
+    let ins = match nets.incoming.try_lock() {
+        Ok(mut ins) => std::mem::take(&mut *ins),
+        Err(_) => vec![],
+    };
+    for item in ins {
+        match item {
+            NetworkingAction::Print(s) => {
+                info!("net says {}", s);
+            }
+            NetworkingAction::Heartbeat => {
+                info!("heartbeat");
+            }
+            NetworkingAction::Welcome {
+                client_id, session, ..
+            } => {
+                info!("connected as {:?}", client_id);
+                connection.client_id = Some(client_id);
+                if let Some(token) = &session {
+                    config.save_session(Some(token));
+                }
+                connection.session = session;
+
+                let room = config.room.clone().unwrap_or_else(|| "default".into());
+                let join = if config.spectate {
+                    NetworkingAction::SpectateRoom(room)
+                } else {
+                    NetworkingAction::JoinRoom(room)
+                };
+                nets.outgoing.lock().unwrap().push(join);
+            }
+            NetworkingAction::Rejected(reason) => {
+                error!("the server turned us away: {:?}", reason);
+                //logs in with the name and secret next time
+                if matches!(
+                    reason,
+                    RejectReason::InvalidToken | RejectReason::TokenExpired
+                ) {
+                    config.save_session(None);
+                }
+            }
+            NetworkingAction::ServerShutdown(reason) => {
+                warn!("the server shut down: {}", reason);
+            }
+            NetworkingAction::RoomJoined {
+                room,
+                player,
+                epoch,
+            } => {
+                info!("joined room {}", room);
+                connection.room = Some(room);
+                connection.player = player;
+                replication.reset = true;
+                replication.epoch = Some(epoch);
+            }
+            NetworkingAction::RoomLeft => {
+                connection.room = None;
+                connection.player = None;
+                replication.reset = true;
+                replication.epoch = None;
+            }
+            NetworkingAction::RoomList(rooms) => {
+                for room in rooms {
+                    info!(
+                        "room {} ({} players, {} spectators)",
+                        room.name, room.players, room.spectators
+                    );
+                }
+            }
+            NetworkingAction::RoomError(e) => warn!("room error: {}", e),
+            NetworkingAction::Replication(message) => replication.messages.push(message),
+            NetworkingAction::Impulse(impulse) => connection.impulses.push(impulse),
+            _ => {}
+        }
+    }
+
     //prevent flooding of the out queue
     timer.0.tick(time.delta());
 
@@ -142,7 +178,7 @@ fn system_update_networking(
         return;
     }
 
-    for (_, mut transform) in player_query.p0().iter() {
+    for (_, transform) in player_query.iter() {
         if let Ok(mut out) = nets.outgoing.try_lock() {
             if out.len() < 3 {
                 out.push(NetworkingAction::Location(
@@ -152,29 +188,202 @@ fn system_update_networking(
             }
         }
     }
+}
+
+fn system_send_to_server(
+    nets: Res<NetworkingQueues>,
+    connection: Res<ServerConnection>,
+    mut to_server: EventReader<ToServer>,
+) {
+    let mut outgoing = nets.outgoing.lock().unwrap();
+    for action in to_server.iter() {
+        if connection.is_connected() {
+            outgoing.push(action.0.clone());
+        }
+    }
+}
+
+fn system_apply_impulses(
+    mut commands: Commands,
+    mut connection: ResMut<ServerConnection>,
+    player: Query<Entity, (With<crate::CameraOrientation>, With<Knockback>)>,
+) {
+    let impulses = std::mem::take(&mut connection.impulses);
+    let entity = match player.iter().next() {
+        Some(e) => e,
+        None => return,
+    };
+    for impulse in impulses {
+        commands.add(ApplyImpulse { entity, impulse });
+    }
+}
+
+///Rebuilds the server state from snapshots, acknowledges them and applies the newest one
+fn system_apply_replication(world: &mut World) {
+    let (messages, reset, epoch) = {
+        let mut incoming = world.resource_mut::<IncomingReplication>();
+        (
+            std::mem::take(&mut incoming.messages),
+            std::mem::replace(&mut incoming.reset, false),
+            incoming.epoch,
+        )
+    };
+
+    if reset {
+        apply_state(world, WorldState::default());
+        *world.resource_mut::<SnapshotHistory>() = SnapshotHistory::default();
+    }
+
+    let epoch = match epoch {
+        Some(e) if !messages.is_empty() => e,
+        _ => return,
+    };
+
+    let mut newest = None;
+    let mut history = world.resource_mut::<SnapshotHistory>();
+    for message in messages {
+        let snapshot = match message {
+            ReplicationMessage::Snapshot(s) => s,
+            _ => continue,
+        };
+
+        //udp can reorder, anything older than what we have is useless. Snapshots from a room we
+        //left can still arrive after joining the next one, their ticks mean nothing here.
+        if snapshot.epoch != epoch || snapshot.tick <= history.latest {
+            continue;
+        }
+
+        let baseline = match snapshot.baseline {
+            Some(tick) => match history.received.iter().find(|(t, _)| *t == tick) {
+                Some((_, state)) => Some(state),
+                //can't rebuild it, the server will fall back to a full snapshot eventually
+                None => continue,
+            },
+            None => None,
+        };
+
+        let state = WorldState::apply(baseline, &snapshot);
+        history.latest = snapshot.tick;
+        history.received.push_back((snapshot.tick, state.clone()));
+        while history.received.len() as u64 > MAX_BASELINE_AGE + 1 {
+            history.received.pop_front();
+        }
+        newest = Some(state);
+    }
+
+    let state = match newest {
+        Some(s) => s,
+        None => return,
+    };
+
+    let tick = history.latest;
+    world
+        .resource::<NetworkingQueues>()
+        .outgoing
+        .lock()
+        .unwrap()
+        .push(NetworkingAction::Replication(ReplicationMessage::Ack {
+            epoch,
+            tick,
+        }));
+
+    apply_state(world, state);
+}
+
+///Spawns, updates and despawns the local copies of replicated entities to match `state`
+fn apply_state(world: &mut World, state: WorldState) {
+    let registry = world.resource::<ReplicationRegistry>().clone();
+    let own_player = world.resource::<ServerConnection>().player;
+    let old = std::mem::take(&mut world.resource_mut::<SnapshotHistory>().applied);
+
+    for id in old.0.keys().filter(|id| !state.0.contains_key(id)) {
+        if let Some(entity) = world.resource_mut::<NetEntityMap>().0.remove(id) {
+            if world.get_entity(entity).is_some() {
+                bevy::hierarchy::despawn_with_children_recursive(world, entity);
+            }
+        }
+    }
 
-    for (_, mut transform) in player_query.p1().iter_mut() {
-        let ins = std::mem::replace(&mut *nets.incoming.try_lock().unwrap(), Vec::new());
-        for item in ins.iter() {
-            match item {
-                NetworkingAction::Print(s) => {
-                    info!("net says {}", s);
-                }
-                NetworkingAction::Location(rot, tran) => {
-                    transform.rotation = *rot;
-                    transform.translation = *tran;
-                }
-                NetworkingAction::Heartbeat => {
-                    info!("heartbeat");
-                }
+    for (id, components) in state.0.iter() {
+        //our own player is simulated locally
+        if Some(*id) == own_player {
+            continue;
+        }
+
+        let mut previous = old.0.get(id);
+        let entity = match world.resource::<NetEntityMap>().0.get(id) {
+            Some(e) if world.get_entity(*e).is_some() => *e,
+            _ => {
+                let e = world.spawn().insert(*id).id();
+                world.resource_mut::<NetEntityMap>().0.insert(*id, e);
+                previous = None;
+                e
+            }
+        };
+
+        for (kind, value) in components.iter() {
+            if previous.and_then(|p| p.get(kind)) == Some(value) {
+                continue;
+            }
+            if let Some(r) = registry.get(*kind) {
+                r.apply(world, entity, value.clone());
             }
         }
     }
+
+    world.resource_mut::<SnapshotHistory>().applied = state;
+}
+
+///Replicated entities only come with their data, give them something to render
+fn system_attach_replicated_visuals(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    assets_server: Res<AssetServer>,
+    query: Query<(Entity, &Transform, Option<&Enemy>), Added<NetId>>,
+) {
+    for (entity, transform, enemy) in query.iter() {
+        let material = match enemy {
+            Some(_) => Handle::<StandardMaterial>::default(),
+            None => materials.add(StandardMaterial::from(Color::rgb(0.0, 0.5, 1.0))),
+        };
+
+        commands.entity(entity).insert_bundle(PbrBundle {
+            mesh: assets_server.load("cube.gltf#Mesh0/Primitive0"),
+            material,
+            transform: *transform,
+            ..Default::default()
+        });
+    }
 }
 
 pub fn build(app: &mut App) {
     app.init_resource::<NetworkingQueues>()
+        .init_resource::<ServerConnection>()
+        .init_resource::<IncomingReplication>()
+        .init_resource::<SnapshotHistory>()
+        .init_resource::<NetEntityMap>()
+        .insert_resource(default_registry())
+        .add_event::<ToServer>()
         .insert_resource(NetworkingTimer(Timer::from_seconds(1.0 / 120.0, true)))
-        .add_startup_system(setup_networking)
-        .add_system(system_update_networking);
+        .add_system_to_stage(CoreStage::PreUpdate, system_update_networking)
+        .add_system_to_stage(
+            CoreStage::PreUpdate,
+            system_apply_replication.exclusive_system().at_end(),
+        )
+        .add_system(system_send_to_server)
+        .add_system(system_apply_impulses)
+        .add_system(system_attach_replicated_visuals);
+
+    //the config is read during startup, connect once it's there
+    app.add_startup_system_to_stage(StartupStage::PostStartup, setup_hello);
+
+    #[cfg(not(target_arch = "wasm32"))]
+    app.add_startup_system_to_stage(StartupStage::PostStartup, native::setup_networking);
+
+    #[cfg(target_arch = "wasm32")]
+    app.add_startup_system_to_stage(
+        StartupStage::PostStartup,
+        web::setup_networking.exclusive_system(),
+    )
+    .add_system_to_stage(CoreStage::PostUpdate, web::system_flush_outgoing);
 }
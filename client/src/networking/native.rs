@@ -0,0 +1,276 @@
+use bevy::prelude::*;
+use message_io::{
+    network::{Endpoint, NetEvent, Transport},
+    node::{self, NodeEvent, NodeHandler},
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::{sync::Arc, sync::Mutex, time::Duration, time::Instant};
+
+use shared::crypto::{self, Handshake, Session};
+use shared::fragment::{Fragmenter, Reassembler};
+use shared::net::{self, ClientId};
+use shared::NetworkingAction;
+
+use super::{NetworkQueue, NetworkingQueues};
+use crate::config::{Config, TransportKind};
+
+///Connects from a thread of its own, messages go through the queues both ways
+pub fn setup_networking(mut netqueues: ResMut<NetworkingQueues>, config: Res<Config>) {
+    let transport = config.transport.unwrap_or(TransportKind::Tcp);
+    let addr = config.server.clone().unwrap_or_else(|| {
+        let port = match transport {
+            TransportKind::Tcp => net::DEFAULT_PORT,
+            TransportKind::WebSocket => net::DEFAULT_WEBSOCKET_PORT,
+        };
+        format!("127.0.0.1:{}", port)
+    });
+
+    let (inc, out) = (netqueues.incoming.clone(), netqueues.outgoing.clone());
+    let encrypt = match (config.encrypt, &config.server_key) {
+        (false, _) => Encryption::Off,
+        (true, None) => Encryption::Unpinned,
+        (true, Some(key)) => match crypto::key_from_hex(key) {
+            Some(key) => Encryption::Pinned(key),
+            None => {
+                error!("server_key isn't a valid key, not connecting");
+                return;
+            }
+        },
+    };
+    let jh = thread::spawn(move || start_player(inc, out, transport, addr, encrypt));
+    netqueues.setup = true;
+}
+
+enum Encryption {
+    Off,
+    ///Trusts the key the server sends, only safe from eavesdroppers
+    Unpinned,
+    ///The server has to hold the secret half of this key
+    Pinned([u8; 32]),
+}
+
+///Everything that goes through the unreliable channel once it is bound
+fn is_unreliable(action: &NetworkingAction) -> bool {
+    matches!(action, NetworkingAction::Replication(_))
+}
+
+///Opens the connection to the server, and the udp channel next to it for transports that have one
+fn connect(
+    handler: &NodeHandler<()>,
+    transport: TransportKind,
+    addr: &str,
+) -> Option<(Endpoint, Option<Endpoint>)> {
+    let reliable = match transport {
+        TransportKind::Tcp => Transport::FramedTcp,
+        TransportKind::WebSocket => Transport::Ws,
+    };
+
+    let (server, _) = match handler.network().connect(reliable, addr) {
+        Ok(d) => d,
+        Err(_) => {
+            info!("failed to connect to active server");
+            return None;
+        }
+    };
+
+    if transport == TransportKind::WebSocket {
+        return Some((server, None));
+    }
+
+    match handler.network().connect(Transport::Udp, addr) {
+        Ok((server_udp, _)) => Some((server, Some(server_udp))),
+        Err(_) => {
+            info!("failed to open udp to active server");
+            None
+        }
+    }
+}
+
+///Encodes the action, sealing it if we have a session
+fn frame(session: &Mutex<Option<Session>>, udp: bool, action: &NetworkingAction) -> Vec<u8> {
+    let data = net::encode(action);
+    match session.lock().unwrap().as_mut() {
+        Some(session) if udp => session.udp.seal(&data),
+        Some(session) => session.tcp.seal(&data),
+        None => data,
+    }
+}
+
+fn start_player(
+    inc: NetworkQueue,
+    out: NetworkQueue,
+    transport: TransportKind,
+    addr: String,
+    encrypt: Encryption,
+) {
+    info!("starting player");
+    let (handler, listener) = node::split::<()>();
+
+    let (server, server_udp) = match connect(&handler, transport, &addr) {
+        Some(endpoints) => endpoints,
+        None => return,
+    };
+
+    //set once connected, and the key exchange is done if we encrypt
+    let connected = Arc::new(AtomicBool::new(false));
+    let udp_bound = Arc::new(AtomicBool::new(false));
+    let client_id: Arc<Mutex<Option<(ClientId, u64)>>> = Default::default();
+    let session: Arc<Mutex<Option<Session>>> = Default::default();
+    let (send_connected, send_udp_bound, send_client_id, send_session) = (
+        connected.clone(),
+        udp_bound.clone(),
+        client_id.clone(),
+        session.clone(),
+    );
+    let h2 = handler.clone();
+
+    let mut i = 0;
+    let mut fragmenter = Fragmenter::default();
+    std::thread::spawn(move || {
+        loop {
+            std::thread::sleep(Duration::from_millis((1000.0f32 / 128.0).floor() as u64));
+
+            if !send_connected.load(Ordering::Relaxed) {
+                continue;
+            }
+
+            i += 1;
+            let heartbeat = frame(&send_session, false, &NetworkingAction::Heartbeat);
+            h2.network().send(server, &heartbeat);
+
+            let udp_bound = send_udp_bound.load(Ordering::Relaxed);
+            if let (false, Some(udp), Some((client_id, key))) =
+                (udp_bound, server_udp, *send_client_id.lock().unwrap())
+            {
+                let hello = net::encode(&NetworkingAction::UdpHello { client_id, key });
+                let hello = match send_session.lock().unwrap().as_mut() {
+                    Some(session) => session.udp.seal_udp_hello(client_id.0, &hello),
+                    None => hello,
+                };
+                h2.network().send(udp, &hello);
+            }
+
+            //empty the outs queue because we're using it now
+            let outs = std::mem::take(&mut *out.lock().unwrap());
+
+            for action in outs {
+                let udp = match server_udp {
+                    Some(udp) if udp_bound && is_unreliable(&action) => udp,
+                    _ => {
+                        h2.network()
+                            .send(server, &frame(&send_session, false, &action));
+                        continue;
+                    }
+                };
+
+                match fragmenter.split(frame(&send_session, true, &action)) {
+                    Some(datagrams) => {
+                        for datagram in datagrams {
+                            h2.network().send(udp, &datagram);
+                        }
+                    }
+                    //too big for udp, send it reliably instead
+                    None => {
+                        h2.network()
+                            .send(server, &frame(&send_session, false, &action));
+                    }
+                }
+            }
+        }
+    });
+
+    //connecting blocks until the connection is up, so the server can be talked to right away
+    info!("connected to server");
+    let mut handshake = None;
+    if !matches!(encrypt, Encryption::Off) {
+        //nothing else goes out until the server answers with its key
+        let ours = Handshake::default();
+        let exchange = NetworkingAction::KeyExchange {
+            key: ours.public_key(),
+            identity: None,
+        };
+        handler.network().send(server, &net::encode(&exchange));
+        handshake = Some(ours);
+    } else {
+        connected.store(true, Ordering::Relaxed);
+    }
+
+    let mut reassembler = Reassembler::default();
+    listener.for_each(move |event| match event {
+        NodeEvent::Signal(_s) => {
+            info!("signal...");
+        }
+        NodeEvent::Network(net_event) => match net_event {
+            NetEvent::Message(endpoint, data) => {
+                let udp = Some(endpoint) == server_udp;
+                let data = if udp {
+                    match reassembler.receive(data, Instant::now()) {
+                        Some(d) => d,
+                        None => return,
+                    }
+                } else {
+                    data.to_vec()
+                };
+                let data = match session.lock().unwrap().as_mut() {
+                    Some(session) if udp => session.udp.open(&data),
+                    Some(session) => session.tcp.open(&data),
+                    None => Ok(data),
+                };
+                let data = match data {
+                    Ok(d) => d,
+                    Err(e) => return info!("server sent a bad frame: {:?}", e),
+                };
+
+                if udp {
+                    udp_bound.store(true, Ordering::Relaxed);
+                }
+
+                match net::decode(&data) {
+                    Ok(NetworkingAction::KeyExchange {
+                        key: theirs,
+                        identity,
+                    }) => {
+                        let ours = match handshake.take() {
+                            Some(h) => h,
+                            None => return,
+                        };
+                        let server_key = match (&encrypt, identity) {
+                            (Encryption::Pinned(key), _) => *key,
+                            (_, Some(key)) => {
+                                warn!("server_key isn't set, the server can't be verified");
+                                key
+                            }
+                            (_, None) => return info!("the server didn't send its key"),
+                        };
+                        match ours.finish_client(theirs, server_key) {
+                            Ok(s) => {
+                                info!("encrypted session established");
+                                *session.lock().unwrap() = Some(s);
+                                connected.store(true, Ordering::Relaxed);
+                            }
+                            Err(e) => info!("key exchange failed: {:?}", e),
+                        }
+                    }
+                    Ok(action) => {
+                        if let NetworkingAction::Welcome {
+                            client_id: id,
+                            udp_key,
+                            ..
+                        } = action
+                        {
+                            *client_id.lock().unwrap() = Some((id, udp_key));
+                        }
+                        inc.lock().unwrap().push(action)
+                    }
+                    Err(e) => info!("server sent an unknown packet: {}", e),
+                }
+            }
+            NetEvent::Disconnected(_) => {
+                info!("disconnected from server",);
+                connected.store(false, Ordering::Relaxed);
+            }
+            _ => {}
+        },
+    });
+}
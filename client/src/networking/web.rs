@@ -0,0 +1,93 @@
+use bevy::prelude::*;
+use js_sys::{ArrayBuffer, Uint8Array};
+use wasm_bindgen::{closure::Closure, JsCast};
+use web_sys::{BinaryType, CloseEvent, MessageEvent, WebSocket};
+
+use shared::net;
+use shared::NetworkingAction;
+
+use super::NetworkingQueues;
+use crate::config::Config;
+
+///The browser owns the socket and calls us back on the main thread, so there is no networking
+///thread. Outgoing messages are flushed once a frame instead.
+pub struct WebSocketConnection {
+    socket: WebSocket,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    _on_close: Closure<dyn FnMut(CloseEvent)>,
+}
+
+///The server the page was served from, on the default websocket port
+fn default_address() -> String {
+    let host = web_sys::window()
+        .and_then(|w| w.location().hostname().ok())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "127.0.0.1".into());
+    format!("{}:{}", host, net::DEFAULT_WEBSOCKET_PORT)
+}
+
+pub fn setup_networking(world: &mut World) {
+    let addr = world
+        .resource::<Config>()
+        .server
+        .clone()
+        .unwrap_or_else(default_address);
+
+    //pages served over https can only open secure websockets
+    let secure = web_sys::window()
+        .and_then(|w| w.location().protocol().ok())
+        .map(|p| p == "https:")
+        .unwrap_or(false);
+    let url = format!("{}://{}", if secure { "wss" } else { "ws" }, addr);
+
+    info!("starting player");
+    let socket = match WebSocket::new(&url) {
+        Ok(s) => s,
+        Err(e) => return warn!("failed to open websocket to {}: {:?}", url, e),
+    };
+    socket.set_binary_type(BinaryType::Arraybuffer);
+
+    let incoming = world.resource::<NetworkingQueues>().incoming.clone();
+    let on_message = Closure::wrap(Box::new(move |event: MessageEvent| {
+        let data = match event.data().dyn_into::<ArrayBuffer>() {
+            Ok(buffer) => Uint8Array::new(&buffer).to_vec(),
+            Err(_) => return info!("server sent a text message"),
+        };
+
+        match net::decode(&data) {
+            Ok(action) => incoming.lock().unwrap().push(action),
+            Err(e) => info!("server sent an unknown packet: {}", e),
+        }
+    }) as Box<dyn FnMut(MessageEvent)>);
+    socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+    let on_close = Closure::wrap(Box::new(move |event: CloseEvent| {
+        info!("disconnected from server: {}", event.reason());
+    }) as Box<dyn FnMut(CloseEvent)>);
+    socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+
+    world.resource_mut::<NetworkingQueues>().setup = true;
+    world.insert_non_send_resource(WebSocketConnection {
+        socket,
+        _on_message: on_message,
+        _on_close: on_close,
+    });
+}
+
+///Sends everything queued this frame. Websockets are reliable only, so there's no udp to bind.
+pub fn system_flush_outgoing(
+    connection: Option<NonSend<WebSocketConnection>>,
+    netqueues: Res<NetworkingQueues>,
+) {
+    let connection = match connection {
+        Some(c) if c.socket.ready_state() == WebSocket::OPEN => c,
+        _ => return,
+    };
+
+    let outs = std::mem::take(&mut *netqueues.outgoing.lock().unwrap());
+    for action in std::iter::once(NetworkingAction::Heartbeat).chain(outs) {
+        if let Err(e) = connection.socket.send_with_u8_array(&net::encode(&action)) {
+            warn!("failed to send to server: {:?}", e);
+        }
+    }
+}
@@ -1,6 +1,12 @@
 use bevy::prelude::*;
 
-use crate::{enemy::Enemy, input::InputEvent};
+use crate::input::InputEvent;
+use crate::networking::ToServer;
+use shared::enemy::Enemy;
+use shared::knockback::{ApplyImpulse, Impulse};
+use shared::movement::{Action, MovementState};
+use shared::replication::NetId;
+use shared::{NetworkingAction, Player};
 
 fn setup() {}
 
@@ -13,15 +19,18 @@ fn update(
     mut inputs: EventReader<InputEvent>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
-    player_query: Query<(&crate::CameraOrientation, &Transform)>,
+    player_query: Query<(&crate::CameraOrientation, &Transform, &MovementState)>,
 ) {
     for input in inputs.iter() {
         info!("{:?}", input);
         if let InputEvent::LRClick | InputEvent::BClick = input {
-            let (orientation, pos) = match player_query.iter().next() {
+            let (orientation, pos, state) = match player_query.iter().next() {
                 Some(p) => p,
                 None => return,
             };
+            if !state.allows(Action::Attack) {
+                continue;
+            }
 
             commands
                 .spawn_bundle(PbrBundle {
@@ -52,17 +61,43 @@ fn move_proj_a(
     }
 }
 
+///Enemies and the other players, our own player never gets a [`Player`] marker
+type Hittable = Or<(With<Enemy>, With<Player>)>;
+
+///A hit knocks the target up and away along the projectile's path, and uses up the projectile.
+///Our own enemies are pushed right here, replicated ones and other players by the server.
 fn move_proj_b(
     mut commands: Commands,
+    mut to_server: EventWriter<ToServer>,
     proj: Query<(Entity, &Proj, &Transform)>,
-    enemy: Query<(Entity, &Enemy, &Transform)>,
+    targets: Query<(Entity, &Transform, Option<&NetId>), Hittable>,
 ) {
-    for (_, _, transform) in proj.iter() {
-        for (ent, _, enemy_trans) in enemy.iter() {
-            if enemy_trans.translation.distance(transform.translation) < 4.0 {
-                commands.entity(ent).despawn_recursive();
-            }
+    for (id, proj_data, transform) in proj.iter() {
+        let hit = targets.iter().find(|(_, target_trans, _)| {
+            target_trans.translation.distance(transform.translation) < 4.0
+        });
+        let (ent, _, net_id) = match hit {
+            Some(h) => h,
+            None => continue,
+        };
+
+        let away = Vec3::new(proj_data.0.x, 0.0, proj_data.0.z).normalize_or_zero();
+        let impulse = Impulse {
+            velocity: away * 20.0 + Vec3::Y * 8.0,
+            decay: None,
+            control_lock: Some(0.5),
+        };
+        match net_id {
+            Some(target) => to_server.send(ToServer(NetworkingAction::Hit {
+                target: *target,
+                impulse,
+            })),
+            None => commands.add(ApplyImpulse {
+                entity: ent,
+                impulse,
+            }),
         }
+        commands.entity(id).despawn_recursive();
     }
 }
 
@@ -0,0 +1,120 @@
+use bevy::prelude::*;
+
+use shared::replication::NetId;
+use shared::{Physics, PhysicsProperties, Player};
+
+use crate::config::Config;
+use crate::networking::NetEntityMap;
+use crate::utils::{RotatableVector, Vec2toVec3};
+use crate::CameraOrientation;
+
+///meters per second when flying around freely
+const FLY_SPEED: f32 = 30.0;
+
+///Marks the camera rig of a client watching a room. It either follows one of the room's players
+///or flies around freely when `following` is `None`.
+#[derive(Component, Default)]
+pub struct Spectator {
+    pub following: Option<NetId>,
+}
+
+///The local player, before it's turned into a camera rig
+type LocalPlayer = (With<CameraOrientation>, With<Physics>);
+
+///Spectators don't get a player, so the local one is turned into a camera rig instead
+fn setup_spectator(
+    mut commands: Commands,
+    config: Res<Config>,
+    mut player_query: Query<(Entity, &mut Visibility), LocalPlayer>,
+) {
+    if !config.spectate {
+        return;
+    }
+
+    for (entity, mut visibility) in player_query.iter_mut() {
+        visibility.is_visible = false;
+        commands
+            .entity(entity)
+            .remove::<Physics>()
+            .remove::<PhysicsProperties>()
+            .insert(Spectator::default());
+    }
+}
+
+fn next_player(current: Option<NetId>, mut players: Vec<NetId>) -> Option<NetId> {
+    players.sort();
+    match current {
+        Some(current) => players
+            .iter()
+            .find(|id| **id > current)
+            .or_else(|| players.first())
+            .copied(),
+        None => players.first().copied(),
+    }
+}
+
+fn system_update_spectator(
+    time: Res<Time>,
+    keyboard_input: Res<Input<KeyCode>>,
+    config: Res<Config>,
+    net_entities: Res<NetEntityMap>,
+    players: Query<&NetId, With<Player>>,
+    targets: Query<&Transform, Without<Spectator>>,
+    mut spectators: Query<(&mut Spectator, &CameraOrientation, &mut Transform)>,
+) {
+    let (mut spectator, cam, mut transform) = match spectators.iter_mut().next() {
+        Some(s) => s,
+        None => return,
+    };
+
+    let [cycle, free] = config.specials;
+    if keyboard_input.just_pressed(cycle) {
+        spectator.following = next_player(spectator.following, players.iter().copied().collect());
+    }
+    if keyboard_input.just_pressed(free) {
+        spectator.following = None;
+    }
+
+    if let Some(id) = spectator.following {
+        let target = net_entities.0.get(&id).and_then(|e| targets.get(*e).ok());
+        match target {
+            Some(target) => transform.translation = target.translation,
+            //they left or went out of view, stay where we are
+            None => spectator.following = None,
+        }
+        return;
+    }
+
+    let mut direction = Vec2::ZERO;
+    let [m_up, m_left, m_down, m_right] = config.movement;
+    if keyboard_input.pressed(m_up) {
+        direction.y += 1.;
+    }
+    if keyboard_input.pressed(m_down) {
+        direction.y -= 1.;
+    }
+    if keyboard_input.pressed(m_right) {
+        direction.x += 1.;
+    }
+    if keyboard_input.pressed(m_left) {
+        direction.x -= 1.;
+    }
+    if direction != Vec2::ZERO {
+        direction = direction.normalize();
+    }
+
+    let mut velocity = direction.rotate_ang(cam.yaw - 90.0f32.to_radians()).xz3();
+    if keyboard_input.pressed(config.jump) {
+        velocity.y += 1.;
+    }
+    if keyboard_input.pressed(config.dash) {
+        velocity.y -= 1.;
+    }
+
+    transform.translation += velocity * FLY_SPEED * time.delta_seconds();
+}
+
+pub fn build(app: &mut App) {
+    app.add_startup_system_to_stage(StartupStage::PostStartup, setup_spectator)
+        .add_system(system_update_spectator);
+}
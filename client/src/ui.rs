@@ -1,4 +1,5 @@
 use bevy::prelude::*;
+use shared::movement::{MovementCharges, MovementState};
 
 ///All info Displayed in Debug screen, updated by various systems
 //TODO: should probably be an Arc mutex to help parallelization, not sure if bevy does that by
@@ -8,12 +9,28 @@ pub struct UIDebugInfo {
     pub speed: f32,
     pub updates: usize,
     pub fr: f64,
+    ///The player's, if there is one
+    pub charges: Option<MovementCharges>,
+    pub state: Option<MovementState>,
 }
 
 impl std::fmt::Display for UIDebugInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "{:3.2} m/s walk", self.speed)?;
         writeln!(f, "{:3.0} fps", self.fr)?;
+        if let Some(state) = &self.state {
+            writeln!(f, "{:?}", state)?;
+        }
+        if let Some(charges) = &self.charges {
+            writeln!(
+                f,
+                "{}/{} air jumps {}/{} air dashes",
+                charges.air_jumps.current,
+                charges.air_jumps.max,
+                charges.air_dashes.current,
+                charges.air_dashes.max
+            )?;
+        }
 
         Ok(())
     }
@@ -70,8 +87,18 @@ fn system_update_debug_info(info: Res<UIDebugInfo>, mut text: Query<(&mut Text,
     }
 }
 
+fn system_update_movement_info(
+    mut info: ResMut<UIDebugInfo>,
+    player: Query<(&MovementCharges, &MovementState)>,
+) {
+    let player = player.iter().next();
+    info.charges = player.map(|(charges, _)| *charges);
+    info.state = player.map(|(_, state)| *state);
+}
+
 pub fn build(app: &mut App) {
     app.init_resource::<UIDebugInfo>()
         .add_startup_system(setup_debug_info)
+        .add_system(system_update_movement_info)
         .add_system(system_update_debug_info);
 }
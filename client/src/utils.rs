@@ -76,3 +76,53 @@ impl Vec2toVec3 for Vec2 {
         Vec3::new(self.x, y, self.y)
     }
 }
+
+///Flat shaded wedge matching a [`shared::collision::Collider::Ramp`] of the same size
+pub fn ramp_mesh(half_extents: Vec3, rises_towards: Vec2) -> Mesh {
+    use bevy::render::render_resource::PrimitiveTopology;
+
+    let d = rises_towards;
+    let along =
+        Vec3::new(d.x, 0.0, d.y) * (d.x.abs() * half_extents.x + d.y.abs() * half_extents.z);
+    let across =
+        Vec3::new(-d.y, 0.0, d.x) * (d.y.abs() * half_extents.x + d.x.abs() * half_extents.z);
+    let up = Vec3::Y * half_extents.y;
+
+    let bottom = |s: f32, c: f32| along * s + across * c - up;
+    let top = |c: f32| along + across * c + up;
+    let faces = [
+        vec![
+            bottom(-1., -1.),
+            bottom(1., -1.),
+            bottom(1., 1.),
+            bottom(-1., 1.),
+        ],
+        vec![bottom(1., -1.), bottom(1., 1.), top(1.), top(-1.)],
+        vec![bottom(-1., -1.), bottom(-1., 1.), top(1.), top(-1.)],
+        vec![bottom(-1., -1.), bottom(1., -1.), top(-1.)],
+        vec![bottom(-1., 1.), bottom(1., 1.), top(1.)],
+    ];
+    //somewhere inside, to tell which way faces point
+    let inside = along * 0.5 - up * 0.5;
+
+    let mut positions = vec![];
+    let mut normals = vec![];
+    for face in &faces {
+        for i in 1..face.len() - 1 {
+            let (a, mut b, mut c) = (face[0], face[i], face[i + 1]);
+            let mut normal = (b - a).cross(c - a).normalize();
+            if normal.dot(a - inside) < 0.0 {
+                std::mem::swap(&mut b, &mut c);
+                normal = -normal;
+            }
+            positions.extend([a, b, c].map(|v| v.to_array()));
+            normals.extend([normal.to_array(); 3]);
+        }
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0f32, 0.0]; positions.len()]);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh
+}
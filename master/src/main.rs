@@ -0,0 +1,298 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use shared::fragment::Fragmenter;
+use shared::master::{
+    self, MasterMessage, ServerInfo, ServerListing, DEFAULT_MASTER_PORT, HEARTBEAT_SECONDS,
+    MISSED_HEARTBEATS,
+};
+
+///More servers than this are ignored until some expire, so heartbeats can't fill memory
+const MAX_SERVERS: usize = 256;
+///Lists are cut short to fit in this many bytes, servers past that aren't sent
+const MAX_LIST_BYTES: usize = 48 * 1024;
+///A challenge cookie stays valid for one to two of these
+const COOKIE_WINDOW: Duration = Duration::from_secs(30);
+
+///Makes the cookies a client has to send back before it gets the list. They can't be guessed
+///without the key, and are only sent to the address they were made for.
+struct Cookies {
+    key: RandomState,
+    started: Instant,
+}
+
+impl Default for Cookies {
+    fn default() -> Self {
+        Self {
+            key: RandomState::new(),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Cookies {
+    fn window(&self, now: Instant) -> u64 {
+        now.duration_since(self.started).as_secs() / COOKIE_WINDOW.as_secs()
+    }
+
+    fn make(&self, ip: IpAddr, window: u64) -> u32 {
+        self.key.hash_one((ip, window)) as u32
+    }
+
+    fn current(&self, ip: IpAddr, now: Instant) -> u32 {
+        self.make(ip, self.window(now))
+    }
+
+    ///Cookies from the previous window still count, so one made just before it ends works
+    fn check(&self, ip: IpAddr, cookie: u32, now: Instant) -> bool {
+        let window = self.window(now);
+        cookie == self.make(ip, window) || (window > 0 && cookie == self.make(ip, window - 1))
+    }
+}
+
+///Encodes as much of the list as fits in [`MAX_LIST_BYTES`]
+fn encode_list(mut list: Vec<ServerListing>) -> Vec<u8> {
+    let empty = master::encode(&MasterMessage::List(vec![])).len();
+    let mut size = empty;
+    let fits = list
+        .iter()
+        .take_while(|listing| {
+            //the listing and the comma before it
+            let alone = master::encode(&MasterMessage::List(vec![(*listing).clone()])).len();
+            size += alone - empty + 1;
+            size <= MAX_LIST_BYTES
+        })
+        .count();
+    if fits < list.len() {
+        eprintln!("{} servers didn't fit in the list", list.len() - fits);
+        list.truncate(fits);
+    }
+    master::encode(&MasterMessage::List(list))
+}
+
+///Every game server that sent a heartbeat recently
+#[derive(Default)]
+struct Registry {
+    servers: HashMap<SocketAddr, (ServerInfo, Instant)>,
+}
+
+impl Registry {
+    fn heartbeat(&mut self, from: SocketAddr, info: ServerInfo, now: Instant) {
+        self.expire(now);
+
+        let addr = SocketAddr::new(from.ip(), info.port);
+        let info = info.truncated();
+        if !self.servers.contains_key(&addr) {
+            if self.servers.len() >= MAX_SERVERS {
+                return;
+            }
+            println!("{} registered as {}", addr, info.name);
+        }
+        self.servers.insert(addr, (info, now));
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let timeout = Duration::from_secs(HEARTBEAT_SECONDS * MISSED_HEARTBEATS);
+        self.servers.retain(|addr, (_, seen)| {
+            let keep = now.duration_since(*seen) < timeout;
+            if !keep {
+                println!("{} stopped sending heartbeats", addr);
+            }
+            keep
+        });
+    }
+
+    fn list(&mut self, now: Instant) -> Vec<ServerListing> {
+        self.expire(now);
+
+        let mut list: Vec<ServerListing> = self
+            .servers
+            .iter()
+            .map(|(addr, (info, _))| ServerListing {
+                addr: *addr,
+                info: info.clone(),
+            })
+            .collect();
+        list.sort_by(|a, b| a.info.name.cmp(&b.info.name).then(a.addr.cmp(&b.addr)));
+        list
+    }
+}
+
+fn serve(socket: UdpSocket) {
+    let mut registry = Registry::default();
+    let cookies = Cookies::default();
+    let mut fragmenter = Fragmenter::default();
+    let mut buf = [0; 2048];
+
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(r) => r,
+            Err(e) => {
+                eprintln!("failed to receive: {}", e);
+                continue;
+            }
+        };
+
+        match master::decode(&buf[..len]) {
+            Ok(MasterMessage::Heartbeat(info)) => registry.heartbeat(from, info, Instant::now()),
+            Ok(MasterMessage::Query { cookie }) => {
+                let now = Instant::now();
+                //the list is many times the size of the query, it only goes to addresses that
+                //proved they can receive there
+                let reply = match cookie {
+                    Some(cookie) if cookies.check(from.ip(), cookie, now) => {
+                        encode_list(registry.list(now))
+                    }
+                    _ => master::encode(&MasterMessage::Challenge(cookies.current(from.ip(), now))),
+                };
+                let datagrams = match fragmenter.split(reply) {
+                    Some(d) => d,
+                    None => {
+                        eprintln!("the list for {} doesn't fit in datagrams", from);
+                        continue;
+                    }
+                };
+                for datagram in datagrams {
+                    if let Err(e) = socket.send_to(&datagram, from) {
+                        eprintln!("failed to answer {}: {}", from, e);
+                    }
+                }
+            }
+            Ok(MasterMessage::Challenge(_) | MasterMessage::List(_)) => {}
+            Err(_) => eprintln!("{} sent an unknown packet", from),
+        }
+    }
+}
+
+fn main() {
+    let addr = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| format!("0.0.0.0:{}", DEFAULT_MASTER_PORT));
+
+    let socket = match UdpSocket::bind(&addr) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("couldn't listen on {}: {}", addr, e);
+            std::process::exit(1);
+        }
+    };
+
+    println!("master server listening on {}", addr);
+    serve(socket);
+}
+
+#[test]
+fn loopback() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let master_addr = socket.local_addr().unwrap();
+    std::thread::spawn(move || serve(socket));
+
+    //enough servers that the list has to be fragmented
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    for port in 7000..7050 {
+        let info = ServerInfo {
+            name: format!("server {}", port),
+            map: "arena".into(),
+            players: 0,
+            port,
+        };
+        let heartbeat = master::encode(&MasterMessage::Heartbeat(info));
+        server.send_to(&heartbeat, master_addr).unwrap();
+    }
+    //a later heartbeat updates the listing instead of adding another
+    let info = ServerInfo {
+        name: "server 7000".into(),
+        map: "arena".into(),
+        players: 3,
+        port: 7000,
+    };
+    let heartbeat = master::encode(&MasterMessage::Heartbeat(info.clone()));
+    server.send_to(&heartbeat, master_addr).unwrap();
+
+    //udp on loopback keeps the order, so the query is answered after every heartbeat
+    let list = master::query(master_addr, Duration::from_secs(5)).unwrap();
+    assert_eq!(list.len(), 50);
+    assert_eq!(
+        list[0],
+        ServerListing {
+            addr: "127.0.0.1:7000".parse().unwrap(),
+            info,
+        }
+    );
+}
+
+#[test]
+fn expiry() {
+    let mut registry = Registry::default();
+    let start = Instant::now();
+    let info = ServerInfo {
+        name: "practice".into(),
+        map: "arena".into(),
+        players: 1,
+        port: 7777,
+    };
+
+    registry.heartbeat("10.0.0.1:50000".parse().unwrap(), info, start);
+    let listed = registry.list(start);
+    assert_eq!(listed[0].addr, "10.0.0.1:7777".parse().unwrap());
+
+    let later = start + Duration::from_secs(HEARTBEAT_SECONDS * MISSED_HEARTBEATS);
+    assert!(registry.list(later).is_empty());
+}
+
+#[test]
+fn long_names_truncated() {
+    let mut registry = Registry::default();
+    let now = Instant::now();
+    let info = ServerInfo {
+        name: "ä".repeat(1000),
+        map: "m".repeat(1000),
+        players: 0,
+        port: 7777,
+    };
+
+    registry.heartbeat("10.0.0.1:50000".parse().unwrap(), info, now);
+    let listed = &registry.list(now)[0].info;
+    assert_eq!(listed.name.chars().count(), master::MAX_NAME_CHARS);
+    assert_eq!(listed.map.chars().count(), master::MAX_MAP_CHARS);
+}
+
+#[test]
+fn list_capped() {
+    let listing = |port| ServerListing {
+        addr: SocketAddr::new([10, 0, 0, 1].into(), port),
+        info: ServerInfo {
+            //the longest listings there can be, four bytes a character
+            name: "\u{1F600}".repeat(master::MAX_NAME_CHARS),
+            map: "\u{1F600}".repeat(master::MAX_MAP_CHARS),
+            players: 0,
+            port,
+        },
+    };
+    let encoded = encode_list((0..MAX_SERVERS as u16).map(listing).collect());
+    assert!(encoded.len() <= MAX_LIST_BYTES);
+    match master::decode(&encoded) {
+        Ok(MasterMessage::List(list)) => assert!(!list.is_empty() && list.len() < MAX_SERVERS),
+        other => panic!("expected a list, got {:?}", other),
+    }
+}
+
+#[test]
+fn challenge() {
+    let cookies = Cookies::default();
+    let now = Instant::now();
+    let ip: IpAddr = [10, 0, 0, 1].into();
+    let cookie = cookies.current(ip, now);
+
+    assert!(cookies.check(ip, cookie, now + COOKIE_WINDOW));
+    assert!(!cookies.check(ip, cookie, now + COOKIE_WINDOW * 2));
+    assert!(!cookies.check([10, 0, 0, 2].into(), cookie, now));
+
+    //the challenge can't be used to send more than was asked for
+    let query = master::encode(&MasterMessage::Query { cookie: None });
+    let answer = master::encode(&MasterMessage::Challenge(u32::MAX));
+    assert!(answer.len() <= query.len());
+}
@@ -0,0 +1,215 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use shared::net::{ClientId, Credentials, RejectReason};
+
+use crate::config::ServerConfig;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+///The players allowed on the server and the key session tokens are signed with
+#[derive(Deserialize, Serialize)]
+pub struct AuthFile {
+    pub token_key: String,
+    ///name -> shared secret
+    pub players: BTreeMap<String, String>,
+}
+
+impl AuthFile {
+    ///Creates an empty file with a fresh token key if there is none yet
+    pub fn load_or_create<P: AsRef<Path>>(file: &P) -> Self {
+        if let Ok(contents) = std::fs::read_to_string(file) {
+            return serde_yaml::from_str(&contents).expect("Couldn't read auth file");
+        }
+
+        let key: [u8; 32] = rand::random();
+        let auth = AuthFile {
+            token_key: hex::encode(key),
+            players: BTreeMap::new(),
+        };
+        let contents = serde_yaml::to_string(&auth).expect("AuthFile is always serializable");
+        std::fs::write(file, contents).expect("couldn't open new auth file for writing");
+        auth
+    }
+
+    fn mac(&self, data: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(self.token_key.as_bytes())
+            .expect("hmac takes keys of any size");
+        mac.update(data.as_bytes());
+        mac
+    }
+
+    ///Tokens look like `name.expiry.signature`, expiry being unix seconds
+    pub fn issue_token(&self, name: &str, expires: u64) -> String {
+        let payload = format!("{}.{}", name, expires);
+        let signature = hex::encode(self.mac(&payload).finalize().into_bytes());
+        format!("{}.{}", payload, signature)
+    }
+
+    ///Returns the name the token was issued to
+    pub fn verify_token(&self, token: &str, now: u64) -> Result<String, RejectReason> {
+        let (payload, signature) = token.rsplit_once('.').ok_or(RejectReason::InvalidToken)?;
+        let signature = hex::decode(signature).map_err(|_| RejectReason::InvalidToken)?;
+        self.mac(payload)
+            .verify_slice(&signature)
+            .map_err(|_| RejectReason::InvalidToken)?;
+
+        //names may contain dots, the expiry can't
+        let (name, expires) = payload.rsplit_once('.').ok_or(RejectReason::InvalidToken)?;
+        let expires: u64 = expires.parse().map_err(|_| RejectReason::InvalidToken)?;
+        if expires < now {
+            return Err(RejectReason::TokenExpired);
+        }
+
+        Ok(name.to_string())
+    }
+
+    pub fn check_secret(&self, name: &str, secret: &str) -> Result<(), RejectReason> {
+        let expected = self.players.get(name).ok_or(RejectReason::UnknownName)?;
+
+        //comparing the macs of both is constant time, so timing doesn't give the secret away
+        let expected = self.mac(expected).finalize().into_bytes();
+        self.mac(secret)
+            .verify_slice(&expected)
+            .map_err(|_| RejectReason::WrongSecret)
+    }
+}
+
+///Decides which connections are let in. Without an auth file everyone is.
+pub struct Auth {
+    file: Option<AuthFile>,
+    session_seconds: u64,
+    require_encryption: bool,
+    ///Every accepted client and the name it logged in as
+    sessions: HashMap<ClientId, Option<String>>,
+}
+
+impl Auth {
+    pub fn from_config(config: &ServerConfig) -> Self {
+        Self {
+            file: config.auth_file.as_ref().map(AuthFile::load_or_create),
+            session_seconds: config.session_hours * 60 * 60,
+            require_encryption: config.require_encryption,
+            sessions: HashMap::new(),
+        }
+    }
+
+    ///Accepts or rejects a client's hello. Authenticated clients get a fresh session token.
+    pub fn login(
+        &mut self,
+        client: ClientId,
+        credentials: &Credentials,
+        encrypted: bool,
+    ) -> Result<Option<String>, RejectReason> {
+        if self.require_encryption && !encrypted {
+            return Err(RejectReason::EncryptionRequired);
+        }
+
+        let file = match &self.file {
+            Some(f) => f,
+            None => {
+                self.sessions.insert(client, None);
+                return Ok(None);
+            }
+        };
+
+        let name = match credentials {
+            Credentials::Anonymous => return Err(RejectReason::CredentialsRequired),
+            Credentials::Secret { name, secret } => {
+                file.check_secret(name, secret)?;
+                name.clone()
+            }
+            Credentials::Token(token) => file.verify_token(token, now())?,
+        };
+
+        if self.sessions.values().any(|n| n.as_ref() == Some(&name)) {
+            return Err(RejectReason::NameInUse);
+        }
+
+        let token = file.issue_token(&name, now() + self.session_seconds);
+        self.sessions.insert(client, Some(name));
+        Ok(Some(token))
+    }
+
+    pub fn is_accepted(&self, client: ClientId) -> bool {
+        self.sessions.contains_key(&client)
+    }
+
+    ///The name the client logged in as, if the server has authentication turned on
+    pub fn name(&self, client: ClientId) -> Option<&str> {
+        self.sessions.get(&client)?.as_deref()
+    }
+
+    pub fn logout(&mut self, client: ClientId) {
+        self.sessions.remove(&client);
+    }
+}
+
+///The name a token was issued to, without checking it's valid
+pub fn token_name(token: &str) -> Option<&str> {
+    let (payload, _) = token.rsplit_once('.')?;
+    let (name, _) = payload.rsplit_once('.')?;
+    Some(name)
+}
+
+///`server issue-token <name> [hours]` prints a token for a player that isn't in the auth file
+pub fn print_token(config: &ServerConfig, args: &[String]) {
+    let file = match &config.auth_file {
+        Some(f) => AuthFile::load_or_create(f),
+        None => return eprintln!("auth_file isn't set in the server config"),
+    };
+    let name = match args.first() {
+        Some(n) => n,
+        None => return eprintln!("usage: server issue-token <name> [hours]"),
+    };
+    let hours = match args.get(1).map(|h| h.parse::<u64>()) {
+        Some(Ok(h)) => h,
+        Some(Err(_)) => return eprintln!("hours has to be a whole number"),
+        None => config.session_hours,
+    };
+
+    println!("{}", file.issue_token(name, now() + hours * 60 * 60));
+}
+
+#[test]
+fn session_tokens() {
+    let file = AuthFile {
+        token_key: "key".into(),
+        players: [("john".to_string(), "hunter2".to_string())].into(),
+    };
+
+    let token = file.issue_token("some.name", 100);
+    assert_eq!(file.verify_token(&token, 50), Ok("some.name".to_string()));
+    assert_eq!(
+        file.verify_token(&token, 150),
+        Err(RejectReason::TokenExpired)
+    );
+
+    let forged = token.replace(".100.", ".999.");
+    assert_eq!(
+        file.verify_token(&forged, 50),
+        Err(RejectReason::InvalidToken)
+    );
+
+    assert_eq!(file.check_secret("john", "hunter2"), Ok(()));
+    assert_eq!(
+        file.check_secret("john", "hunter3"),
+        Err(RejectReason::WrongSecret)
+    );
+    assert_eq!(
+        file.check_secret("bob", "hunter2"),
+        Err(RejectReason::UnknownName)
+    );
+}
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use shared::net::{ClientId, RejectReason};
+
+use crate::auth;
+use crate::config::ServerConfig;
+
+///Who a ban or allow entry applies to, written `name:<name>`, `token:<token>` or `ip:<ip>`.
+///Tokens are reissued on every login, so a token stands for the name it was issued to.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub enum Target {
+    Name(String),
+    Ip(IpAddr),
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = s.split_once(':').ok_or_else(|| {
+            format!(
+                "{} should look like name:<name>, token:<token> or ip:<ip>",
+                s
+            )
+        })?;
+        match kind {
+            "name" => Ok(Target::Name(value.into())),
+            "token" => auth::token_name(value)
+                .map(|name| Target::Name(name.into()))
+                .ok_or_else(|| format!("{} isn't a token", value)),
+            "ip" => value
+                .parse()
+                .map(Target::Ip)
+                .map_err(|_| format!("{} isn't an ip address", value)),
+            _ => Err(format!("unknown target kind {}", kind)),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::Name(n) => write!(f, "name:{}", n),
+            Target::Ip(ip) => write!(f, "ip:{}", ip),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Ban {
+    pub target: Target,
+    pub reason: String,
+    ///Unix seconds, the ban is permanent if unset
+    pub expires: Option<u64>,
+}
+
+///What is known about a client when it says hello. Names are only verified if the server has an
+///auth file, otherwise it's whatever the client claims.
+#[derive(Clone, Debug, Default)]
+pub struct Identity {
+    pub name: Option<String>,
+    pub ip: Option<IpAddr>,
+}
+
+impl Identity {
+    fn matches(&self, target: &Target) -> bool {
+        match target {
+            Target::Name(n) => self.name.as_ref() == Some(n),
+            Target::Ip(ip) => self.ip == Some(*ip),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Default)]
+pub struct AccessFile {
+    #[serde(default)]
+    pub bans: Vec<Ban>,
+    ///Only let in players matching `allowed`
+    #[serde(default)]
+    pub whitelist: bool,
+    #[serde(default)]
+    pub allowed: Vec<Target>,
+}
+
+///Bans and the allow list, saved to disk on every change
+pub struct AccessList {
+    path: String,
+    file: AccessFile,
+    ///Everyone let in, so new bans can kick them
+    connected: HashMap<ClientId, Identity>,
+}
+
+impl AccessList {
+    pub fn from_config(config: &ServerConfig) -> Self {
+        let file = match std::fs::read_to_string(&config.access_file) {
+            Ok(contents) => serde_yaml::from_str(&contents).expect("Couldn't read access file"),
+            Err(_) => AccessFile::default(),
+        };
+
+        Self {
+            path: config.access_file.clone(),
+            file,
+            connected: HashMap::new(),
+        }
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let contents =
+            serde_yaml::to_string(&self.file).expect("AccessFile is always serializable");
+        std::fs::write(&self.path, contents)
+            .map_err(|e| format!("couldn't save {}: {}", self.path, e))
+    }
+
+    ///Why the client may not join, if it may not
+    pub fn check(&self, who: &Identity, now: u64) -> Result<(), RejectReason> {
+        let ban = self
+            .file
+            .bans
+            .iter()
+            .filter(|b| b.expires.map(|e| e > now).unwrap_or(true))
+            .find(|b| who.matches(&b.target));
+        if let Some(ban) = ban {
+            return Err(RejectReason::Banned {
+                reason: ban.reason.clone(),
+                expires: ban.expires,
+            });
+        }
+
+        if self.file.whitelist && !self.file.allowed.iter().any(|t| who.matches(t)) {
+            return Err(RejectReason::NotAllowed);
+        }
+        Ok(())
+    }
+
+    ///Checks the client and remembers it if it's let in
+    pub fn admit(&mut self, client: ClientId, who: Identity, now: u64) -> Result<(), RejectReason> {
+        self.check(&who, now)?;
+        self.connected.insert(client, who);
+        Ok(())
+    }
+
+    pub fn leave(&mut self, client: ClientId) {
+        self.connected.remove(&client);
+    }
+
+    ///Connected clients that wouldn't be let in anymore, after a ban or allow list change
+    pub fn turned_away(&self, now: u64) -> Vec<(ClientId, RejectReason)> {
+        self.connected
+            .iter()
+            .filter_map(|(client, who)| Some((*client, self.check(who, now).err()?)))
+            .collect()
+    }
+
+    ///Bans that haven't expired yet
+    pub fn bans(&self, now: u64) -> impl Iterator<Item = &Ban> {
+        self.file
+            .bans
+            .iter()
+            .filter(move |b| b.expires.map(|e| e > now).unwrap_or(true))
+    }
+
+    ///Replaces any earlier ban of the same target, and forgets expired ones
+    pub fn ban(&mut self, ban: Ban, now: u64) -> Result<(), String> {
+        self.file
+            .bans
+            .retain(|b| b.target != ban.target && b.expires.map(|e| e > now).unwrap_or(true));
+        self.file.bans.push(ban);
+        self.save()
+    }
+
+    ///Returns whether the target was banned
+    pub fn unban(&mut self, target: &Target) -> Result<bool, String> {
+        let before = self.file.bans.len();
+        self.file.bans.retain(|b| &b.target != target);
+        let removed = self.file.bans.len() != before;
+        self.save()?;
+        Ok(removed)
+    }
+
+    pub fn allow(&mut self, target: Target) -> Result<(), String> {
+        if !self.file.allowed.contains(&target) {
+            self.file.allowed.push(target);
+        }
+        self.save()
+    }
+
+    ///Returns whether the target was allowed
+    pub fn disallow(&mut self, target: &Target) -> Result<bool, String> {
+        let before = self.file.allowed.len();
+        self.file.allowed.retain(|t| t != target);
+        let removed = self.file.allowed.len() != before;
+        self.save()?;
+        Ok(removed)
+    }
+
+    pub fn set_whitelist(&mut self, on: bool) -> Result<(), String> {
+        self.file.whitelist = on;
+        self.save()
+    }
+
+    pub fn allowed(&self) -> &[Target] {
+        &self.file.allowed
+    }
+}
+
+#[test]
+fn bans_and_whitelist() {
+    let mut access = AccessList {
+        path: String::new(),
+        file: AccessFile::default(),
+        connected: HashMap::new(),
+    };
+    let griefer = Identity {
+        name: Some("griefer".into()),
+        ip: "10.0.0.2".parse().ok(),
+    };
+    let friend = Identity {
+        name: Some("friend".into()),
+        ..Default::default()
+    };
+
+    assert_eq!("ip:10.0.0.2".parse(), Ok(Target::Ip(griefer.ip.unwrap())));
+    assert!("ip:nope".parse::<Target>().is_err());
+    assert!("griefer".parse::<Target>().is_err());
+    //whatever token they log in with next, it's the same name
+    assert_eq!(
+        "token:grief.er.1700000000.abcd".parse(),
+        Ok(Target::Name("grief.er".into()))
+    );
+    assert!("token:griefer".parse::<Target>().is_err());
+
+    access.admit(ClientId(1), griefer.clone(), 0).unwrap();
+    access.file.bans.push(Ban {
+        target: "ip:10.0.0.2".parse().unwrap(),
+        reason: "griefing".into(),
+        expires: Some(100),
+    });
+    let banned = RejectReason::Banned {
+        reason: "griefing".into(),
+        expires: Some(100),
+    };
+    //the connected griefer gets kicked, and can't come back until the ban runs out
+    assert_eq!(access.turned_away(50), vec![(ClientId(1), banned.clone())]);
+    assert_eq!(access.check(&griefer, 50), Err(banned));
+    assert_eq!(access.check(&griefer, 100), Ok(()));
+
+    access.file.whitelist = true;
+    access.file.allowed.push(Target::Name("friend".into()));
+    assert_eq!(access.check(&friend, 0), Ok(()));
+    assert_eq!(access.check(&griefer, 100), Err(RejectReason::NotAllowed));
+}
@@ -0,0 +1,77 @@
+use serde::Deserialize;
+use std::path::Path;
+
+///Server settings, read from `server_config.yaml` next to the binary
+#[derive(Deserialize)]
+pub struct ServerConfig {
+    ///Shown in the server browser
+    pub server_name: String,
+    pub map: String,
+    ///`host:port` of the master server to list this server on, unlisted if unset
+    pub master: Option<String>,
+
+    ///tcp and udp both listen here
+    pub port: u16,
+    ///Browser clients connect here, websockets are disabled if unset
+    pub websocket_port: Option<u16>,
+    ///Allowed players and their secrets, anyone can join if unset
+    pub auth_file: Option<String>,
+    ///How long the session tokens handed out at login stay valid
+    pub session_hours: u64,
+    ///Turn away clients that don't do the key exchange
+    pub require_encryption: bool,
+    ///The server's key for encrypted sessions, created on first start. Players put its public
+    ///half in their config so nobody can pose as the server.
+    pub identity_file: String,
+    ///Bans and the allow list, edited from the server console
+    pub access_file: String,
+    ///How often a dead listener thread is brought back before the server gives up
+    pub listener_restarts: u32,
+    ///Serves `/status` as json and `/metrics` for prometheus, disabled if unset
+    pub status_addr: Option<String>,
+    ///The last status is written here as json when the server stops, not kept if unset
+    pub stats_file: Option<String>,
+
+    pub default_room: String,
+    pub max_rooms: usize,
+    pub room_tick_rate: f64,
+
+    pub relevance_radius: f32,
+    pub relevance_hysteresis: f32,
+}
+
+const DEFAULT_CONFIG: &str = include_str!("../assets/default_server_config.yaml");
+
+impl ServerConfig {
+    pub fn load_or_create<P: AsRef<Path>>(file: &P) -> Self {
+        let config = std::fs::read_to_string(file).unwrap_or_else(move |_| {
+            let mut f = std::fs::File::create(file).expect("couldn't open new config for writing");
+            use std::io::Write;
+            f.write_all(DEFAULT_CONFIG.as_bytes())
+                .expect("Couldn't write new config ??");
+            DEFAULT_CONFIG.into()
+        });
+
+        ServerConfig::load_from_string(&config)
+    }
+
+    pub fn load_from_string(config: &str) -> Self {
+        serde_yaml::from_str(config).expect("Couldn't read config")
+    }
+
+    pub fn load_or_create_default() -> Self {
+        let file = "./server_config.yaml";
+        ServerConfig::load_or_create(&file)
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig::load_from_string(DEFAULT_CONFIG)
+    }
+}
+
+#[test]
+fn default_config_valid() {
+    ServerConfig::default();
+}
@@ -0,0 +1,145 @@
+use std::io::BufRead;
+use std::sync::{Arc, Mutex};
+
+use bevy::prelude::*;
+
+use shared::NetworkingAction;
+
+use crate::auth::now;
+use crate::bans::{AccessList, Ban, Target};
+use crate::net::Net;
+
+const HELP: &str = "commands:
+  ban <target> [hours] [reason]  targets are name:<name>, token:<token> or ip:<ip>,
+                                 a token stands for the name it was issued to
+  unban <target>
+  bans
+  allow <target>
+  disallow <target>
+  allowed
+  whitelist on|off";
+
+///Lines typed into the server's terminal, read on a thread of their own
+#[derive(Default)]
+pub struct Console {
+    lines: Arc<Mutex<Vec<String>>>,
+}
+
+pub fn build(app: &mut App) {
+    let console = Console::default();
+    let lines = console.lines.clone();
+    std::thread::spawn(move || {
+        //ends once stdin closes, like when the server runs as a service
+        for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+            lines.lock().unwrap().push(line);
+        }
+    });
+
+    app.insert_resource(console).add_system(system_console);
+}
+
+fn target(arg: Option<&str>) -> Result<Target, String> {
+    arg.ok_or("missing target")?.parse()
+}
+
+///Runs one command, returning what to print
+fn run(line: &str, access: &mut AccessList) -> Result<String, String> {
+    let mut args = line.split_whitespace();
+    let command = match args.next() {
+        Some(c) => c,
+        None => return Ok(String::new()),
+    };
+
+    match command {
+        "ban" => {
+            let target = target(args.next())?;
+            let mut rest = args.peekable();
+            let hours = match rest.peek().and_then(|h| h.parse::<u64>().ok()) {
+                Some(h) => {
+                    rest.next();
+                    Some(h)
+                }
+                None => None,
+            };
+            let reason = rest.collect::<Vec<_>>().join(" ");
+
+            let ban = Ban {
+                target: target.clone(),
+                reason,
+                expires: hours.map(|h| now() + h * 60 * 60),
+            };
+            access.ban(ban, now())?;
+            Ok(match hours {
+                Some(h) => format!("banned {} for {} hours", target, h),
+                None => format!("banned {}", target),
+            })
+        }
+        "unban" => {
+            let target = target(args.next())?;
+            match access.unban(&target)? {
+                true => Ok(format!("unbanned {}", target)),
+                false => Err(format!("{} isn't banned", target)),
+            }
+        }
+        "bans" => Ok(access
+            .bans(now())
+            .map(|b| match b.expires {
+                Some(e) => format!("{} until {} ({})", b.target, e, b.reason),
+                None => format!("{} ({})", b.target, b.reason),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")),
+        "allow" => {
+            let target = target(args.next())?;
+            access.allow(target.clone())?;
+            Ok(format!("allowed {}", target))
+        }
+        "disallow" => {
+            let target = target(args.next())?;
+            match access.disallow(&target)? {
+                true => Ok(format!("disallowed {}", target)),
+                false => Err(format!("{} isn't allowed", target)),
+            }
+        }
+        "allowed" => Ok(access
+            .allowed()
+            .iter()
+            .map(Target::to_string)
+            .collect::<Vec<_>>()
+            .join("\n")),
+        "whitelist" => {
+            let on = match args.next() {
+                Some("on") => true,
+                Some("off") => false,
+                _ => return Err("whitelist on|off".into()),
+            };
+            access.set_whitelist(on)?;
+            Ok(format!("whitelist {}", if on { "on" } else { "off" }))
+        }
+        "help" => Ok(HELP.into()),
+        _ => Err(format!("unknown command {}, try help", command)),
+    }
+}
+
+fn system_console(console: Res<Console>, net: Res<Net>, mut access: ResMut<AccessList>) {
+    let lines = std::mem::take(&mut *console.lines.lock().unwrap());
+    if lines.is_empty() {
+        return;
+    }
+
+    for line in lines {
+        match run(&line, &mut access) {
+            Ok(out) if out.is_empty() => {}
+            Ok(out) => info!("{}", out),
+            Err(e) => warn!("{}", e),
+        }
+    }
+
+    //whoever the changes apply to is kicked right away
+    for (client, reason) in access.turned_away(now()) {
+        info!("kicking {:?}: {:?}", client, reason);
+        net.send(client, &NetworkingAction::Rejected(reason));
+        net.disconnect(client);
+        access.leave(client);
+    }
+}
@@ -0,0 +1,259 @@
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+
+use shared::enemy::{self, Enemy};
+use shared::knockback::{ApplyImpulse, Impulse, Knockback};
+use shared::net::ClientId;
+use shared::replication::{NetId, Replicated, ReplicationMessage};
+use shared::{Health, NetworkingAction, Player};
+
+use crate::replication::ServerReplication;
+use crate::rooms::RoomName;
+
+const MAX_ENEMIES: usize = 32;
+
+///Clients report the pushes of their hits, stronger ones are cut down to this
+const MAX_HIT_SPEED: f32 = 25.0;
+const MAX_HIT_LOCK: f64 = 1.0;
+
+///Messages produced by game systems this tick, sent at the end of the frame
+#[derive(Default)]
+pub struct Outbox {
+    pub reliable: Vec<(ClientId, NetworkingAction)>,
+    pub unreliable: Vec<(ClientId, NetworkingAction)>,
+}
+
+impl Outbox {
+    pub fn send(&mut self, client: ClientId, action: NetworkingAction) {
+        self.reliable.push((client, action));
+    }
+
+    pub fn send_unreliable(&mut self, client: ClientId, action: NetworkingAction) {
+        self.unreliable.push((client, action));
+    }
+}
+
+///The entity each connected client controls
+#[derive(Default)]
+pub struct Players(pub HashMap<ClientId, Entity>);
+
+///Clients watching the room without a player of their own
+#[derive(Default)]
+pub struct Spectators(pub HashSet<ClientId>);
+
+struct EnemySpawnTimer(Timer);
+
+pub enum RoomEvent {
+    Joined(ClientId),
+    Spectating(ClientId),
+    Message(ClientId, NetworkingAction),
+    Left(ClientId),
+}
+
+///Everything the process routed to this room since its last tick
+#[derive(Default)]
+pub struct Inbox(pub Vec<RoomEvent>);
+
+///Rooms don't share the process [`Time`], each one advances by a fixed step per tick
+pub struct RoomClock {
+    pub tick: u64,
+    pub delta: f32,
+}
+
+///Who is in the room, and what goes out to them
+#[derive(SystemParam)]
+struct RoomState<'w, 's> {
+    name: Res<'w, RoomName>,
+    players: ResMut<'w, Players>,
+    spectators: ResMut<'w, Spectators>,
+    replication: ResMut<'w, ServerReplication>,
+    outbox: ResMut<'w, Outbox>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+///What a client's hit can push
+type Hittable = (With<Knockback>, Or<(With<Enemy>, With<Player>)>);
+
+fn system_handle_inbox(
+    mut commands: Commands,
+    mut inbox: ResMut<Inbox>,
+    mut room: RoomState,
+    mut transforms: Query<&mut Transform>,
+    targets: Query<(Entity, &NetId), Hittable>,
+) {
+    for event in inbox.0.drain(..) {
+        match event {
+            RoomEvent::Joined(client) => {
+                let id = room.replication.allocate_id();
+                let player = commands
+                    .spawn()
+                    .insert_bundle((
+                        Transform::default(),
+                        Knockback::default(),
+                        Health::new(100.0),
+                        Player,
+                        Replicated,
+                        id,
+                    ))
+                    .id();
+
+                room.players.0.insert(client, player);
+                let epoch = room.replication.add_client(client);
+                room.outbox.send(
+                    client,
+                    NetworkingAction::RoomJoined {
+                        room: room.name.0.clone(),
+                        player: Some(id),
+                        epoch,
+                    },
+                );
+            }
+            RoomEvent::Spectating(client) => {
+                //without a player there's no center for interest, so they see everything
+                room.spectators.0.insert(client);
+                let epoch = room.replication.add_client(client);
+                room.outbox.send(
+                    client,
+                    NetworkingAction::RoomJoined {
+                        room: room.name.0.clone(),
+                        player: None,
+                        epoch,
+                    },
+                );
+            }
+            RoomEvent::Message(client, action) => match action {
+                NetworkingAction::Location(rotation, translation) => {
+                    let player = match room.players.0.get(&client) {
+                        Some(p) => *p,
+                        None => continue,
+                    };
+                    if let Ok(mut transform) = transforms.get_mut(player) {
+                        transform.rotation = rotation;
+                        transform.translation = translation;
+                    }
+                }
+                NetworkingAction::Replication(ReplicationMessage::Ack { epoch, tick }) => {
+                    room.replication.ack(client, epoch, tick);
+                }
+                NetworkingAction::Hit { target, impulse } => {
+                    //spectators have nothing to shoot with, and nobody can hit what they can't see
+                    let shooter = match room.players.0.get(&client) {
+                        Some(p) => *p,
+                        None => continue,
+                    };
+                    let entity = targets
+                        .iter()
+                        .find(|(_, id)| **id == target)
+                        .map(|(e, _)| e)
+                        .filter(|e| *e != shooter && room.replication.is_relevant(client, target));
+                    match (entity, checked_hit(impulse)) {
+                        (Some(entity), Some(impulse)) => {
+                            commands.add(ApplyImpulse { entity, impulse })
+                        }
+                        _ => info!("{:?} sent an impossible hit on {:?}", client, target),
+                    }
+                }
+                _ => info!("{:?} sent an unexpected packet", client),
+            },
+            RoomEvent::Left(client) => {
+                if let Some(player) = room.players.0.remove(&client) {
+                    commands.entity(player).despawn_recursive();
+                }
+                room.spectators.0.remove(&client);
+                room.replication.remove_client(client);
+            }
+        }
+    }
+}
+
+///Bounds a push a client reported, `None` if it can't be applied at all
+fn checked_hit(impulse: Impulse) -> Option<Impulse> {
+    let valid = impulse.velocity.is_finite()
+        && impulse.decay.unwrap_or(0.0) >= 0.0
+        && impulse.control_lock.unwrap_or(0.0).is_finite();
+    if !valid {
+        return None;
+    }
+
+    Some(Impulse {
+        velocity: impulse.velocity.clamp_length_max(MAX_HIT_SPEED),
+        decay: impulse.decay,
+        control_lock: impulse.control_lock.map(|l| l.clamp(0.0, MAX_HIT_LOCK)),
+    })
+}
+
+fn system_spawn_enemies(
+    mut commands: Commands,
+    clock: Res<RoomClock>,
+    mut spawn_timer: ResMut<EnemySpawnTimer>,
+    enemies: Query<&Enemy>,
+) {
+    spawn_timer.0.tick(Duration::from_secs_f32(clock.delta));
+
+    if spawn_timer.0.just_finished() && enemies.iter().count() < MAX_ENEMIES {
+        commands.spawn().insert_bundle((
+            Transform::default(),
+            Enemy::default(),
+            Knockback::default(),
+            Health::new(20.0),
+            Replicated,
+        ));
+    }
+}
+
+fn system_update_enemies(
+    clock: Res<RoomClock>,
+    mut enemies: Query<(&mut Enemy, &mut Knockback, &mut Transform)>,
+) {
+    for (mut enemy, mut knockback, mut trans) in enemies.iter_mut() {
+        enemy::knocked_back(&mut enemy, &mut knockback, &mut trans, clock.delta);
+        enemy::wander(&mut enemy, &mut trans, clock.delta);
+    }
+}
+
+///Players move on their clients, pushes they got here are sent there
+fn system_forward_knockback(
+    players: Res<Players>,
+    mut outbox: ResMut<Outbox>,
+    mut knockbacks: Query<&mut Knockback>,
+) {
+    for (client, player) in players.0.iter() {
+        let mut knockback = match knockbacks.get_mut(*player) {
+            Ok(k) if *k != Knockback::default() => k,
+            _ => continue,
+        };
+        let knockback = std::mem::take(&mut *knockback);
+        let impulse = Impulse {
+            velocity: knockback.velocity,
+            decay: knockback.decay,
+            control_lock: knockback.pending_lock,
+        };
+        outbox.send(*client, NetworkingAction::Impulse(impulse));
+    }
+}
+
+pub fn insert_resources(world: &mut World, tick_rate: f64) {
+    world.init_resource::<Outbox>();
+    world.init_resource::<Inbox>();
+    world.init_resource::<Players>();
+    world.init_resource::<Spectators>();
+    world.insert_resource(RoomClock {
+        tick: 0,
+        delta: (1.0 / tick_rate) as f32,
+    });
+    world.insert_resource(EnemySpawnTimer(Timer::from_seconds(2.0, true)));
+}
+
+///Rooms run their stages single threaded, so systems run in the order they are added here
+pub fn add_systems(stage: SystemStage) -> SystemStage {
+    stage
+        .with_system(system_handle_inbox)
+        .with_system(system_spawn_enemies)
+        .with_system(system_update_enemies)
+        .with_system(system_forward_knockback)
+}
@@ -0,0 +1,125 @@
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+
+use shared::replication::NetId;
+
+use crate::config::ServerConfig;
+
+///Decides which replicated entities each client gets told about
+#[derive(Clone)]
+pub struct InterestSettings {
+    pub radius: f32,
+    pub hysteresis: f32,
+}
+
+impl InterestSettings {
+    pub fn from_config(config: &ServerConfig) -> Self {
+        Self {
+            radius: config.relevance_radius,
+            hysteresis: config.relevance_hysteresis,
+        }
+    }
+
+    ///Entities enter at `radius` but only leave at `radius + hysteresis`, so something walking
+    ///along the edge doesn't get spawned and despawned every tick
+    pub fn is_relevant(&self, distance: f32, was_relevant: bool) -> bool {
+        if was_relevant {
+            distance <= self.radius + self.hysteresis
+        } else {
+            distance <= self.radius
+        }
+    }
+
+    ///Updates the set of entities relevant to a client whose player is at `center`. Without a
+    ///center everything is relevant, and so is anything without a position.
+    pub fn update(
+        &self,
+        relevant: &mut HashSet<NetId>,
+        center: Option<Vec3>,
+        positions: &HashMap<NetId, Option<Vec3>>,
+    ) {
+        relevant.retain(|id| positions.contains_key(id));
+
+        for (id, position) in positions.iter() {
+            let keep = match (center, position) {
+                (Some(center), Some(position)) => {
+                    self.is_relevant(center.distance(*position), relevant.contains(id))
+                }
+                _ => true,
+            };
+
+            if keep {
+                relevant.insert(*id);
+            } else {
+                relevant.remove(id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: InterestSettings = InterestSettings {
+        radius: 10.0,
+        hysteresis: 2.0,
+    };
+
+    #[test]
+    fn inside_radius_relevant() {
+        assert!(SETTINGS.is_relevant(5.0, false));
+        assert!(SETTINGS.is_relevant(10.0, false));
+        assert!(SETTINGS.is_relevant(5.0, true));
+    }
+
+    #[test]
+    fn hysteresis_band_keeps_state() {
+        assert!(SETTINGS.is_relevant(11.0, true));
+        assert!(!SETTINGS.is_relevant(11.0, false));
+        assert!(SETTINGS.is_relevant(12.0, true));
+    }
+
+    #[test]
+    fn past_outer_radius_dropped() {
+        assert!(!SETTINGS.is_relevant(12.5, true));
+        assert!(!SETTINGS.is_relevant(12.5, false));
+    }
+
+    #[test]
+    fn enter_and_leave_across_ticks() {
+        let near = NetId(1);
+        let walker = NetId(2);
+        let unplaced = NetId(3);
+        let mut relevant = HashSet::default();
+        let tick = |relevant: &mut HashSet<NetId>, walker_at: f32| {
+            let before = relevant.clone();
+            let positions: HashMap<_, _> = [
+                (near, Some(Vec3::X)),
+                (walker, Some(Vec3::X * walker_at)),
+                (unplaced, None),
+            ]
+            .into_iter()
+            .collect();
+            SETTINGS.update(relevant, Some(Vec3::ZERO), &positions);
+            let entered: Vec<_> = relevant.difference(&before).copied().collect();
+            let left: Vec<_> = before.difference(relevant).copied().collect();
+            (entered.len(), left)
+        };
+
+        //everything in range, or without a position, enters on the first tick
+        assert_eq!(tick(&mut relevant, 9.0), (3, vec![]));
+        //walking out into the band doesn't despawn it, past it does
+        assert_eq!(tick(&mut relevant, 11.0), (0, vec![]));
+        assert_eq!(tick(&mut relevant, 13.0), (0, vec![walker]));
+        //coming back into the band isn't enough to enter again, the radius is
+        assert_eq!(tick(&mut relevant, 11.0), (0, vec![]));
+        assert!(!relevant.contains(&walker));
+        assert_eq!(tick(&mut relevant, 10.0), (1, vec![]));
+        assert!(relevant.contains(&walker));
+
+        //despawned entities leave too
+        SETTINGS.update(&mut relevant, Some(Vec3::ZERO), &HashMap::default());
+        assert!(relevant.is_empty());
+    }
+}
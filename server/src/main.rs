@@ -1,106 +1,68 @@
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
-};
+use std::time::Duration;
 
+use bevy::app::ScheduleRunnerSettings;
 use bevy::prelude::*;
 
-use message_io::{
-    network::{NetEvent, Transport},
-    node,
-};
-
-use shared::NetworkingAction;
-
-pub struct NetStruct<T: Send + 'static> {
-    pub handler: node::NodeHandler<T>,
-    //pub listener: node::NodeListener<T>,
-    pub is_crashed: Arc<AtomicBool>,
+mod auth;
+mod bans;
+mod config;
+mod console;
+mod game;
+mod interest;
+mod master;
+mod net;
+mod replication;
+mod rooms;
+mod shutdown;
+mod status;
+
+///How often the process polls the network and updates rooms, each room ticks at its own rate
+pub const TICK_RATE: f64 = 120.0;
+
+fn add_networking(app: &mut App) {
+    let net = match net::server(app.world.resource::<config::ServerConfig>()) {
+        Ok(n) => n,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    app.insert_resource(net);
+    shutdown::build(app);
 }
 
-type Net = NetStruct<()>;
-
-fn server() -> Net {
-    let (handler, listener) = node::split::<()>();
-
-    handler
-        .network()
-        .listen(Transport::Tcp, "0.0.0.0:7777")
-        .unwrap();
-    handler
-        .network()
-        .listen(Transport::Udp, "0.0.0.0:7777")
-        .unwrap();
-
-    let listen_handle = handler.clone();
-    let is_crashed = Arc::new(AtomicBool::new(false));
-    let listen_is_crashed = is_crashed.clone();
-
-    std::thread::spawn(move || {
-        info!("Starting server");
-
-        let handler = listen_handle;
-        listener.for_each(|event| match event.network() {
-            NetEvent::Connected(_, _) => println!("connected"),
-            NetEvent::Message(endpoint, data) => {
-                let j = match std::str::from_utf8(data) {
-                    Ok(d) => d,
-                    Err(_) => {
-                        info!("someone sent invalid utf8");
-                        return;
-                    }
-                };
-
-                let _action: NetworkingAction = match serde_json::from_str(j) {
-                    Ok(packet) => packet,
-                    Err(_) => {
-                        info!("someone sent an unknown packet: {}", j);
-                        return;
-                    }
-                };
-
-
-
-                handler.network().send(endpoint, "ack".as_bytes());
-            }
-            NetEvent::Disconnected(_) => println!("disconnected"),
-        });
-
-        error!("Server crashed...");
-        listen_is_crashed.store(true, Ordering::Relaxed);
-    });
+fn main() {
+    let config = config::ServerConfig::load_or_create_default();
 
-    Net {
-        handler,
-        is_crashed,
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("issue-token") {
+        return auth::print_token(&config, &args[2..]);
     }
-}
 
-struct TestECS;
+    let mut app = App::new();
 
-fn startup(mut commands: Commands) {
-    commands.spawn().insert(TestECS);
-}
-
-fn add_networking(app: &mut AppBuilder) {
-    app.insert_resource(server())
-        .add_startup_system(startup.system());
-    //.add_system(check_server.system());
-}
-
-fn main() {
-    //server();
-
-    let mut app = App::build();
-
-    app.add_plugin(bevy::log::LogPlugin)
-        .add_plugin(bevy::core::CorePlugin)
-        .add_plugin(bevy::transform::TransformPlugin)
-        .add_plugin(bevy::diagnostic::DiagnosticsPlugin)
-        .add_plugin(bevy::app::ScheduleRunnerPlugin::default());
+    app.insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(
+        1.0 / TICK_RATE,
+    )))
+    .add_plugin(bevy::log::LogPlugin)
+    .add_plugins(MinimalPlugins)
+    .add_plugin(bevy::transform::TransformPlugin)
+    .add_plugin(bevy::hierarchy::HierarchyPlugin)
+    .add_plugin(bevy::diagnostic::DiagnosticsPlugin);
 
+    app.insert_resource(auth::Auth::from_config(&config))
+        .insert_resource(bans::AccessList::from_config(&config))
+        .insert_resource(config);
 
     add_networking(&mut app);
+    rooms::build(&mut app);
+    status::build(&mut app);
+    master::build(&mut app);
+    console::build(&mut app);
 
     app.run();
+    if shutdown::failed() {
+        std::process::exit(1);
+    }
+    info!("server stopped");
 }
@@ -0,0 +1,62 @@
+use std::net::UdpSocket;
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use shared::master::{self, MasterMessage, ServerInfo, HEARTBEAT_SECONDS};
+
+use crate::config::ServerConfig;
+use crate::rooms::Rooms;
+
+///Keeps the server listed on the master server
+pub struct MasterLink {
+    socket: UdpSocket,
+    master: String,
+    heartbeat: Timer,
+}
+
+pub fn build(app: &mut App) {
+    let master = match &app.world.resource::<ServerConfig>().master {
+        Some(m) => m.clone(),
+        None => return,
+    };
+
+    let socket = match UdpSocket::bind("0.0.0.0:0") {
+        Ok(s) => s,
+        Err(e) => return error!("couldn't open a socket for the master server: {}", e),
+    };
+
+    //the first heartbeat goes out right away
+    let mut heartbeat = Timer::new(Duration::from_secs(HEARTBEAT_SECONDS), true);
+    heartbeat.set_elapsed(heartbeat.duration());
+
+    info!("listing the server on {}", master);
+    app.insert_resource(MasterLink {
+        socket,
+        master,
+        heartbeat,
+    })
+    .add_system(system_heartbeat);
+}
+
+fn system_heartbeat(
+    time: Res<Time>,
+    config: Res<ServerConfig>,
+    rooms: Res<Rooms>,
+    mut link: ResMut<MasterLink>,
+) {
+    if !link.heartbeat.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let info = ServerInfo {
+        name: config.server_name.clone(),
+        map: config.map.clone(),
+        players: rooms.list().iter().map(|r| r.players).sum(),
+        port: config.port,
+    };
+    let heartbeat = master::encode(&MasterMessage::Heartbeat(info));
+    if let Err(e) = link.socket.send_to(&heartbeat, &link.master) {
+        warn!("failed to reach the master server {}: {}", link.master, e);
+    }
+}
@@ -0,0 +1,448 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::time::Instant;
+
+use bevy::prelude::*;
+
+use message_io::{
+    network::{Endpoint, NetEvent, Transport},
+    node::{self, NodeHandler, NodeListener},
+};
+
+use shared::crypto::{self, Handshake, Identity, Session};
+use shared::fragment::{Fragmenter, Reassembler};
+use shared::net::{self, ClientId};
+use shared::NetworkingAction;
+
+use crate::config::ServerConfig;
+
+pub enum ServerEvent {
+    ///A new connection, the server doesn't answer it until it says hello
+    Connected,
+    Message(ClientId, NetworkingAction),
+    Disconnected(ClientId),
+}
+
+#[derive(Default)]
+struct Endpoints {
+    next_id: u64,
+    by_client: HashMap<ClientId, Endpoint>,
+    udp_by_client: HashMap<ClientId, Endpoint>,
+    ///What a client's udp hello has to contain, handed out in the welcome
+    udp_keys: HashMap<ClientId, u64>,
+    by_endpoint: HashMap<Endpoint, ClientId>,
+    ///Clients that asked for encryption, everything they send and get is sealed
+    sessions: HashMap<ClientId, Session>,
+    ///Splits udp frames that don't fit in a datagram
+    fragmenter: Fragmenter,
+    reassemblers: HashMap<ClientId, Reassembler>,
+}
+
+impl Endpoints {
+    ///Forgets every endpoint of the client, returning its connection
+    fn remove(&mut self, client: ClientId) -> Option<Endpoint> {
+        let endpoint = self.by_client.remove(&client)?;
+        self.by_endpoint.remove(&endpoint);
+        self.udp_keys.remove(&client);
+        self.sessions.remove(&client);
+        self.reassemblers.remove(&client);
+        if let Some(udp) = self.udp_by_client.remove(&client) {
+            self.by_endpoint.remove(&udp);
+        }
+        Some(endpoint)
+    }
+
+    ///Encodes the action, sealing it if the client has a session
+    fn frame(&mut self, client: ClientId, udp: bool, action: &NetworkingAction) -> Vec<u8> {
+        let data = net::encode(action);
+        match self.sessions.get_mut(&client) {
+            Some(session) if udp => session.udp.seal(&data),
+            Some(session) => session.tcp.seal(&data),
+            None => data,
+        }
+    }
+
+    ///Udp has no connection, the first datagram from an endpoint says which client it is. The
+    ///hello has to carry the key from the welcome, or open with the client's keys if it has a
+    ///session.
+    fn bind_udp(&mut self, endpoint: Endpoint, data: &[u8]) -> Option<ClientId> {
+        let sealed_by = crypto::udp_hello_sender(data).map(ClientId);
+        let data = match sealed_by {
+            Some(client) => {
+                let session = self.sessions.get_mut(&client)?;
+                session.udp.open_udp_hello(data).ok()?
+            }
+            None => data.to_vec(),
+        };
+
+        let (client, key) = match net::decode(&data) {
+            Ok(NetworkingAction::UdpHello { client_id, key }) => (client_id, key),
+            _ => return None,
+        };
+        if self.udp_keys.get(&client) != Some(&key) {
+            return None;
+        }
+        //encrypted clients have to seal it, so a plaintext hello can't steal their udp
+        match sealed_by {
+            Some(sealer) if sealer != client => return None,
+            None if self.sessions.contains_key(&client) => return None,
+            _ => {}
+        }
+
+        self.udp_by_client.insert(client, endpoint);
+        self.by_endpoint.insert(endpoint, client);
+        Some(client)
+    }
+}
+
+pub struct NetStruct<T: Send + 'static> {
+    pub handler: node::NodeHandler<T>,
+    identity: Arc<Identity>,
+    //pub listener: node::NodeListener<T>,
+    is_crashed: Arc<AtomicBool>,
+    events: Arc<Mutex<Vec<ServerEvent>>>,
+    endpoints: Arc<Mutex<Endpoints>>,
+}
+
+pub type Net = NetStruct<()>;
+
+impl<T: Send + 'static> NetStruct<T> {
+    pub fn send(&self, client: ClientId, action: &NetworkingAction) {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let endpoint = match endpoints.by_client.get(&client) {
+            Some(e) => *e,
+            None => return,
+        };
+
+        //sent while still locked, so frames go out in the order their counters were taken
+        let frame = endpoints.frame(client, false, action);
+        self.handler.network().send(endpoint, &frame);
+    }
+
+    ///Sends over udp once the client has bound its udp endpoint, over its connection until then.
+    ///Frames too big for a datagram are fragmented, or sent reliably if even that's not enough.
+    pub fn send_unreliable(&self, client: ClientId, action: &NetworkingAction) {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let udp = match endpoints.udp_by_client.get(&client) {
+            Some(e) => *e,
+            None => {
+                drop(endpoints);
+                return self.send(client, action);
+            }
+        };
+
+        let frame = endpoints.frame(client, true, action);
+        match endpoints.fragmenter.split(frame) {
+            Some(datagrams) => {
+                for datagram in datagrams {
+                    self.handler.network().send(udp, &datagram);
+                }
+            }
+            None => {
+                drop(endpoints);
+                warn!("{:?} got a message too big for udp", client);
+                self.send(client, action);
+            }
+        }
+    }
+
+    ///Where the client's connection comes from
+    pub fn addr(&self, client: ClientId) -> Option<SocketAddr> {
+        let endpoints = self.endpoints.lock().unwrap();
+        endpoints.by_client.get(&client).map(|e| e.addr())
+    }
+
+    pub fn is_encrypted(&self, client: ClientId) -> bool {
+        self.endpoints
+            .lock()
+            .unwrap()
+            .sessions
+            .contains_key(&client)
+    }
+
+    pub fn udp_key(&self, client: ClientId) -> Option<u64> {
+        self.endpoints
+            .lock()
+            .unwrap()
+            .udp_keys
+            .get(&client)
+            .copied()
+    }
+
+    ///Drops the client's connection. It goes through the same `Disconnected` event as if the
+    ///client had left on its own.
+    pub fn disconnect(&self, client: ClientId) {
+        let removed = self.endpoints.lock().unwrap().remove(client);
+        if let Some(endpoint) = removed {
+            self.handler.network().remove(endpoint.resource_id());
+            self.events
+                .lock()
+                .unwrap()
+                .push(ServerEvent::Disconnected(client));
+        }
+    }
+
+    ///Everything the listener thread received since the last call
+    pub fn drain_events(&self) -> Vec<ServerEvent> {
+        std::mem::take(&mut *self.events.lock().unwrap())
+    }
+}
+
+///The listener couldn't open one of its sockets, usually because the port is taken
+#[derive(Debug)]
+pub struct ListenError {
+    transport: Transport,
+    addr: String,
+    error: std::io::Error,
+}
+
+impl std::fmt::Display for ListenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "couldn't listen for {:?} on {}: {}",
+            self.transport, self.addr, self.error
+        )
+    }
+}
+
+fn listen(config: &ServerConfig) -> Result<(NodeHandler<()>, NodeListener<()>), ListenError> {
+    let (handler, listener) = node::split::<()>();
+
+    let addr = format!("0.0.0.0:{}", config.port);
+    let mut sockets = vec![(Transport::FramedTcp, addr.clone()), (Transport::Udp, addr)];
+    //websocket clients are accepted like tcp ones, they just never bind udp
+    if let Some(port) = config.websocket_port {
+        sockets.push((Transport::Ws, format!("0.0.0.0:{}", port)));
+    }
+
+    for (transport, addr) in sockets {
+        if let Err(error) = handler.network().listen(transport, &addr) {
+            //the sockets opened so far close with the node
+            handler.stop();
+            return Err(ListenError {
+                transport,
+                addr,
+                error,
+            });
+        }
+    }
+
+    Ok((handler, listener))
+}
+
+///Set when the listener thread ends, whether it returned or panicked
+struct CrashFlag(Arc<AtomicBool>);
+
+impl Drop for CrashFlag {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+fn spawn_listener(net: &Net, listener: NodeListener<()>) {
+    let listen_is_crashed = net.is_crashed.clone();
+    let listen_events = net.events.clone();
+    let listen_endpoints = net.endpoints.clone();
+    let listen_handler = net.handler.clone();
+    let identity = net.identity.clone();
+
+    std::thread::spawn(move || {
+        info!("Starting server");
+        let _crash_flag = CrashFlag(listen_is_crashed);
+
+        listener.for_each(|event| match event.network() {
+            NetEvent::Connected(endpoint, _) => {
+                let mut endpoints = listen_endpoints.lock().unwrap();
+                let client = ClientId(endpoints.next_id);
+                endpoints.next_id += 1;
+                endpoints.by_client.insert(client, endpoint);
+                endpoints.by_endpoint.insert(endpoint, client);
+                endpoints.udp_keys.insert(client, rand::random());
+
+                info!("{:?} connected from {}", client, endpoint.addr());
+                listen_events.lock().unwrap().push(ServerEvent::Connected);
+            }
+            NetEvent::Message(endpoint, data) => {
+                let mut endpoints = listen_endpoints.lock().unwrap();
+                let client = match endpoints.by_endpoint.get(&endpoint) {
+                    Some(c) => *c,
+                    None => {
+                        if let Some(client) = endpoints.bind_udp(endpoint, data) {
+                            info!("{:?} bound udp {}", client, endpoint.addr());
+                        }
+                        return;
+                    }
+                };
+
+                //once there's a session plaintext isn't accepted anymore
+                let udp = endpoints.udp_by_client.get(&client) == Some(&endpoint);
+                let data = if udp {
+                    let reassembler = endpoints.reassemblers.entry(client).or_default();
+                    match reassembler.receive(data, Instant::now()) {
+                        Some(d) => d,
+                        None => return,
+                    }
+                } else {
+                    data.to_vec()
+                };
+                let data = match endpoints.sessions.get_mut(&client) {
+                    Some(session) if udp => session.udp.open(&data),
+                    Some(session) => session.tcp.open(&data),
+                    None => Ok(data),
+                };
+                let data = match data {
+                    Ok(d) => d,
+                    Err(e) => return info!("{:?} sent a bad frame: {:?}", client, e),
+                };
+
+                let action = match net::decode(&data) {
+                    Ok(packet) => packet,
+                    Err(_) => {
+                        info!(
+                            "someone sent an unknown packet: {}",
+                            String::from_utf8_lossy(&data)
+                        );
+                        return;
+                    }
+                };
+
+                if let NetworkingAction::KeyExchange { key: theirs, .. } = action {
+                    if udp || endpoints.sessions.contains_key(&client) {
+                        return;
+                    }
+
+                    let handshake = Handshake::default();
+                    let ours = NetworkingAction::KeyExchange {
+                        key: handshake.public_key(),
+                        identity: Some(identity.public_key()),
+                    };
+                    match handshake.finish_server(theirs, &identity) {
+                        Ok(session) => {
+                            //our key goes out in the clear, everything after it is sealed
+                            listen_handler.network().send(endpoint, &net::encode(&ours));
+                            endpoints.sessions.insert(client, session);
+                        }
+                        Err(e) => info!("{:?} failed the key exchange: {:?}", client, e),
+                    }
+                    return;
+                }
+                drop(endpoints);
+
+                listen_events
+                    .lock()
+                    .unwrap()
+                    .push(ServerEvent::Message(client, action));
+            }
+            NetEvent::Disconnected(endpoint) => {
+                let mut endpoints = listen_endpoints.lock().unwrap();
+                let client = endpoints.by_endpoint.get(&endpoint).copied();
+                //already gone if we dropped the connection ourselves
+                if let Some(client) = client {
+                    endpoints.remove(client);
+                    info!("{:?} disconnected", client);
+                    listen_events
+                        .lock()
+                        .unwrap()
+                        .push(ServerEvent::Disconnected(client));
+                }
+            }
+        });
+    });
+}
+
+///Reads the server's identity, or creates one if there is none yet
+fn load_or_create_identity(file: &str) -> Identity {
+    if let Ok(contents) = std::fs::read_to_string(file) {
+        let secret = crypto::key_from_hex(&contents).expect("Couldn't read identity file");
+        return Identity::from_bytes(secret);
+    }
+
+    let identity = Identity::generate();
+    std::fs::write(file, crypto::key_to_hex(&identity.to_bytes()))
+        .expect("couldn't open new identity file for writing");
+    identity
+}
+
+pub fn server(config: &ServerConfig) -> Result<Net, ListenError> {
+    let (handler, listener) = listen(config)?;
+
+    let identity = load_or_create_identity(&config.identity_file);
+    info!(
+        "server key for encrypted sessions: {}",
+        crypto::key_to_hex(&identity.public_key())
+    );
+
+    let net = Net {
+        handler,
+        identity: Arc::new(identity),
+        is_crashed: Default::default(),
+        events: Default::default(),
+        endpoints: Default::default(),
+    };
+    spawn_listener(&net, listener);
+    Ok(net)
+}
+
+impl Net {
+    ///Listens again after the listener thread died. Every client lost its connection with it
+    ///and gets a `Disconnected` event.
+    pub fn restart(&mut self, config: &ServerConfig) -> Result<(), ListenError> {
+        self.handler.stop();
+
+        //a listener that panicked may have poisoned the lock, so it's replaced as a whole
+        let (clients, next_id) = {
+            let old = self.endpoints.lock().unwrap_or_else(|e| e.into_inner());
+            let clients: Vec<ClientId> = old.by_client.keys().copied().collect();
+            (clients, old.next_id)
+        };
+        //ids keep counting up, so a new client is never mistaken for an old one
+        self.endpoints = Arc::new(Mutex::new(Endpoints {
+            next_id,
+            ..Default::default()
+        }));
+        self.events
+            .lock()
+            .unwrap()
+            .extend(clients.into_iter().map(ServerEvent::Disconnected));
+
+        let (handler, listener) = listen(config)?;
+        self.handler = handler;
+        self.is_crashed = Default::default();
+        spawn_listener(self, listener);
+        Ok(())
+    }
+
+    ///Tells every client why the server is going away and closes the sockets
+    pub fn shutdown(&self, reason: &str) {
+        let clients: Vec<ClientId> = self
+            .endpoints
+            .lock()
+            .unwrap()
+            .by_client
+            .keys()
+            .copied()
+            .collect();
+        for client in clients {
+            self.send(
+                client,
+                &NetworkingAction::ServerShutdown(reason.to_string()),
+            );
+        }
+        self.handler.stop();
+    }
+
+    pub fn is_crashed(&self) -> bool {
+        self.is_crashed.load(Ordering::Relaxed)
+    }
+
+    ///What the listener thread sets when it dies
+    #[cfg(test)]
+    pub fn crash(&self) {
+        self.is_crashed.store(true, Ordering::Relaxed);
+    }
+}
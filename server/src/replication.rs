@@ -0,0 +1,248 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+
+use shared::net::ClientId;
+use shared::replication::{
+    default_registry, NetId, Replicated, ReplicationMessage, ReplicationRegistry,
+};
+use shared::snapshot::{WorldState, MAX_BASELINE_AGE};
+use shared::NetworkingAction;
+
+use crate::game::{Outbox, Players};
+use crate::interest::InterestSettings;
+
+///Snapshots sent to a single client that it may still acknowledge
+#[derive(Default)]
+struct ClientView {
+    ///Picked when the client joins, see [`shared::snapshot::Snapshot::epoch`]
+    epoch: u64,
+    acked: Option<u64>,
+    sent: VecDeque<(u64, WorldState, Instant)>,
+    relevant: HashSet<NetId>,
+    ///Smoothed time from sending a snapshot to its ack, includes up to a tick of waiting in the
+    ///room's inbox
+    rtt: Option<Duration>,
+}
+
+impl ClientView {
+    ///The acknowledged state to delta against, if it is recent enough
+    fn baseline(&self, tick: u64) -> Option<(u64, &WorldState)> {
+        let acked = self.acked?;
+        if tick - acked > MAX_BASELINE_AGE {
+            return None;
+        }
+
+        self.sent
+            .iter()
+            .find(|(t, _, _)| *t == acked)
+            .map(|(t, state, _)| (*t, state))
+    }
+}
+
+#[derive(Default)]
+pub struct ServerReplication {
+    next_id: u64,
+    tick: u64,
+    ///State of the previous tick, components that aren't due this tick keep their old value
+    last_state: WorldState,
+    clients: HashMap<ClientId, ClientView>,
+}
+
+impl ServerReplication {
+    pub fn allocate_id(&mut self) -> NetId {
+        self.next_id += 1;
+        NetId(self.next_id)
+    }
+
+    ///Starts sending snapshots to the client, returns the epoch they are tagged with
+    pub fn add_client(&mut self, client: ClientId) -> u64 {
+        let epoch = rand::random();
+        self.clients.insert(
+            client,
+            ClientView {
+                epoch,
+                ..Default::default()
+            },
+        );
+        epoch
+    }
+
+    pub fn remove_client(&mut self, client: ClientId) {
+        self.clients.remove(&client);
+    }
+
+    ///Whether the client currently gets told about the entity
+    pub fn is_relevant(&self, client: ClientId, id: NetId) -> bool {
+        self.clients
+            .get(&client)
+            .map(|view| view.relevant.contains(&id))
+            .unwrap_or(false)
+    }
+
+    pub fn rtt(&self, client: ClientId) -> Option<Duration> {
+        self.clients.get(&client)?.rtt
+    }
+
+    pub fn ack(&mut self, client: ClientId, epoch: u64, tick: u64) {
+        //a late ack for a room the client was in before
+        let view = match self.clients.get_mut(&client) {
+            Some(v) if v.epoch == epoch => v,
+            _ => return,
+        };
+
+        if view.acked.map(|a| tick > a).unwrap_or(true) && tick <= self.tick {
+            view.acked = Some(tick);
+
+            let sent_at = view.sent.iter().find(|(t, _, _)| *t == tick);
+            if let Some((_, _, sent_at)) = sent_at {
+                let sample = sent_at.elapsed();
+                //same smoothing as tcp, one sample moves it an eighth of the way
+                view.rtt = Some(match view.rtt {
+                    Some(rtt) => rtt.mul_f64(0.875) + sample.mul_f64(0.125),
+                    None => sample,
+                });
+            }
+
+            //nothing older than the ack will ever be used as a baseline again
+            while view
+                .sent
+                .front()
+                .map(|(t, _, _)| *t < tick)
+                .unwrap_or(false)
+            {
+                view.sent.pop_front();
+            }
+        }
+    }
+}
+
+type Positions = HashMap<NetId, Option<Vec3>>;
+
+fn collect_state(
+    world: &mut World,
+    registry: &ReplicationRegistry,
+    tick: u64,
+) -> (WorldState, Positions) {
+    let unassigned: Vec<Entity> = world
+        .query_filtered::<Entity, (With<Replicated>, Without<NetId>)>()
+        .iter(world)
+        .collect();
+    for entity in unassigned {
+        let id = world.resource_mut::<ServerReplication>().allocate_id();
+        world.entity_mut(entity).insert(id);
+    }
+
+    let entities: Vec<(Entity, NetId)> = world
+        .query_filtered::<(Entity, &NetId), With<Replicated>>()
+        .iter(world)
+        .map(|(e, id)| (e, *id))
+        .collect();
+
+    let positions = entities
+        .iter()
+        .map(|(entity, id)| (*id, world.get::<Transform>(*entity).map(|t| t.translation)))
+        .collect();
+
+    let last = &world.resource::<ServerReplication>().last_state;
+    let state = entities
+        .into_iter()
+        .map(|(entity, id)| {
+            let previous = last.0.get(&id);
+            let components = registry
+                .iter()
+                .filter_map(|c| {
+                    let due = tick.is_multiple_of(c.priority.interval());
+                    match previous.and_then(|p| p.get(&c.kind)) {
+                        Some(old) if !due => Some((c.kind, old.clone())),
+                        _ => Some((c.kind, c.extract(world, entity)?)),
+                    }
+                })
+                .collect();
+            (id, components)
+        })
+        .collect();
+
+    (WorldState(state), positions)
+}
+
+///Sends every client a snapshot of the entities near its player, delta compressed against the
+///last snapshot it acknowledged
+pub fn system_replicate(world: &mut World) {
+    let registry = world.resource::<ReplicationRegistry>().clone();
+    let tick = world.resource::<ServerReplication>().tick + 1;
+    let (state, positions) = collect_state(world, &registry, tick);
+
+    let centers: HashMap<ClientId, Vec3> = world
+        .resource::<Players>()
+        .0
+        .iter()
+        .filter_map(|(client, player)| {
+            Some((*client, world.get::<Transform>(*player)?.translation))
+        })
+        .collect();
+
+    let interest = world.resource::<InterestSettings>().clone();
+
+    let mut messages = vec![];
+    let mut replication = world.resource_mut::<ServerReplication>();
+    replication.tick = tick;
+
+    for (client, view) in replication.clients.iter_mut() {
+        interest.update(&mut view.relevant, centers.get(client).copied(), &positions);
+        let visible = WorldState(
+            state
+                .0
+                .iter()
+                .filter(|(id, _)| view.relevant.contains(id))
+                .map(|(id, components)| (*id, components.clone()))
+                .collect(),
+        );
+
+        //entities leaving the relevant set show up as removed, entering ones as new
+        let snapshot = visible.delta(view.epoch, tick, view.baseline(tick));
+
+        view.sent.push_back((tick, visible, Instant::now()));
+        //the baseline is always newer than this, see ClientView::baseline
+        while view.sent.len() as u64 > MAX_BASELINE_AGE + 1 {
+            view.sent.pop_front();
+        }
+
+        messages.push((*client, snapshot));
+    }
+    replication.last_state = state;
+
+    let mut outbox = world.resource_mut::<Outbox>();
+    for (client, snapshot) in messages {
+        outbox.send_unreliable(
+            client,
+            NetworkingAction::Replication(ReplicationMessage::Snapshot(snapshot)),
+        );
+    }
+}
+
+pub fn insert_resources(world: &mut World, interest: InterestSettings) {
+    world.insert_resource(default_registry());
+    world.init_resource::<ServerReplication>();
+    world.insert_resource(interest);
+}
+
+#[test]
+fn stale_acks() {
+    let client = ClientId(1);
+    let mut replication = ServerReplication {
+        tick: 10,
+        ..Default::default()
+    };
+    let old = replication.add_client(client);
+    let epoch = replication.add_client(client);
+    assert_ne!(old, epoch);
+
+    //an ack from the room the client was in before joining this one again
+    replication.ack(client, old, 5);
+    assert_eq!(replication.clients[&client].acked, None);
+    replication.ack(client, epoch, 5);
+    assert_eq!(replication.clients[&client].acked, Some(5));
+}
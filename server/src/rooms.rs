@@ -0,0 +1,495 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use bevy::ecs::schedule::Stage;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use shared::net::{ClientId, Credentials, RoomInfo};
+use shared::replication::Replicated;
+use shared::NetworkingAction;
+
+use crate::auth::{self, Auth};
+use crate::bans::{AccessList, Identity};
+use crate::config::ServerConfig;
+use crate::game::{self, Inbox, Outbox, Players, RoomClock, RoomEvent, Spectators};
+use crate::interest::InterestSettings;
+use crate::net::{Net, ServerEvent};
+use crate::replication::{self, ServerReplication};
+
+///A room never runs more than this many ticks in one process update, so a stall doesn't turn
+///into a burst of catch up ticks
+const MAX_TICKS_PER_UPDATE: u32 = 4;
+
+///Rooms other than the default one are closed after being empty this long
+const ROOM_IDLE_SECONDS: f64 = 60.0;
+
+///Room names end up in every room list, so longer ones are turned down
+const MAX_ROOM_NAME_CHARS: usize = 32;
+
+///How many of the latest tick durations a room keeps for the status page
+const TICK_SAMPLES: usize = 512;
+
+///The name of the room a world belongs to
+pub struct RoomName(pub String);
+
+///A single match with its own world, schedule and tick
+pub struct Room {
+    world: World,
+    schedule: Schedule,
+    tick_rate: f64,
+    accumulator: f64,
+    idle: f64,
+    tick_durations: VecDeque<Duration>,
+}
+
+impl Room {
+    pub fn new(name: &str, tick_rate: f64, interest: InterestSettings) -> Self {
+        let mut world = World::new();
+        world.insert_resource(RoomName(name.to_string()));
+        game::insert_resources(&mut world, tick_rate);
+        replication::insert_resources(&mut world, interest);
+
+        let mut schedule = Schedule::default();
+        schedule.add_stage("update", game::add_systems(SystemStage::single_threaded()));
+        schedule.add_stage_after(
+            "update",
+            "replicate",
+            SystemStage::single_threaded()
+                .with_system(replication::system_replicate.exclusive_system()),
+        );
+
+        Self {
+            world,
+            schedule,
+            tick_rate,
+            accumulator: 0.0,
+            idle: 0.0,
+            tick_durations: VecDeque::new(),
+        }
+    }
+
+    pub fn players(&self) -> usize {
+        self.world.resource::<Players>().0.len()
+    }
+
+    pub fn spectators(&self) -> usize {
+        self.world.resource::<Spectators>().0.len()
+    }
+
+    pub fn entities(&self) -> usize {
+        self.world.entities().len() as usize
+    }
+
+    pub fn replicated(&mut self) -> usize {
+        self.world
+            .query_filtered::<Entity, With<Replicated>>()
+            .iter(&self.world)
+            .count()
+    }
+
+    ///Everyone in the room, whether they spectate, and their round trip time once measured
+    pub fn clients(&self) -> Vec<(ClientId, bool, Option<Duration>)> {
+        let replication = self.world.resource::<ServerReplication>();
+        let players = self
+            .world
+            .resource::<Players>()
+            .0
+            .keys()
+            .map(|c| (c, false));
+        let spectators = self
+            .world
+            .resource::<Spectators>()
+            .0
+            .iter()
+            .map(|c| (c, true));
+        players
+            .chain(spectators)
+            .map(|(client, spectating)| (*client, spectating, replication.rtt(*client)))
+            .collect()
+    }
+
+    ///How long the latest ticks took to run
+    pub fn tick_durations(&self) -> &VecDeque<Duration> {
+        &self.tick_durations
+    }
+
+    fn send(&mut self, event: RoomEvent) {
+        self.world.resource_mut::<Inbox>().0.push(event);
+    }
+
+    ///Runs however many ticks fit in `delta` seconds
+    fn update(&mut self, delta: f64, net: &Net) {
+        self.accumulator += delta;
+
+        let step = 1.0 / self.tick_rate;
+        let mut ticks = 0;
+        while self.accumulator >= step && ticks < MAX_TICKS_PER_UPDATE {
+            self.accumulator -= step;
+            ticks += 1;
+
+            self.world.resource_mut::<RoomClock>().tick += 1;
+            let started = Instant::now();
+            self.schedule.run(&mut self.world);
+            self.world.clear_trackers();
+
+            self.tick_durations.push_back(started.elapsed());
+            if self.tick_durations.len() > TICK_SAMPLES {
+                self.tick_durations.pop_front();
+            }
+        }
+        self.accumulator = self.accumulator.min(step);
+
+        if self.players() == 0 {
+            self.idle += delta;
+        } else {
+            self.idle = 0.0;
+        }
+
+        let mut outbox = self.world.resource_mut::<Outbox>();
+        for (client, action) in outbox.reliable.drain(..) {
+            net.send(client, &action);
+        }
+        for (client, action) in outbox.unreliable.drain(..) {
+            net.send_unreliable(client, &action);
+        }
+    }
+}
+
+///Every room in the process and which room each client is in
+pub struct Rooms {
+    rooms: BTreeMap<String, Room>,
+    clients: HashMap<ClientId, String>,
+    default_room: String,
+    max_rooms: usize,
+    tick_rate: f64,
+    interest: InterestSettings,
+}
+
+impl Rooms {
+    pub fn from_config(config: &ServerConfig) -> Self {
+        let interest = InterestSettings::from_config(config);
+        let mut rooms = BTreeMap::new();
+        rooms.insert(
+            config.default_room.clone(),
+            Room::new(
+                &config.default_room,
+                config.room_tick_rate,
+                interest.clone(),
+            ),
+        );
+
+        Self {
+            rooms,
+            clients: HashMap::default(),
+            default_room: config.default_room.clone(),
+            max_rooms: config.max_rooms,
+            tick_rate: config.room_tick_rate,
+            interest,
+        }
+    }
+
+    pub fn list(&self) -> Vec<RoomInfo> {
+        self.rooms
+            .iter()
+            .map(|(name, room)| RoomInfo {
+                name: name.clone(),
+                players: room.players(),
+                spectators: room.spectators(),
+            })
+            .collect()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&String, &mut Room)> {
+        self.rooms.iter_mut()
+    }
+
+    fn create(&mut self, name: &str) -> Result<(), String> {
+        if name.is_empty() {
+            return Err("room names can't be empty".into());
+        }
+        if name.chars().count() > MAX_ROOM_NAME_CHARS {
+            return Err(format!(
+                "room names can't be longer than {} characters",
+                MAX_ROOM_NAME_CHARS
+            ));
+        }
+        if name.chars().any(char::is_control) {
+            return Err("room names can't contain control characters".into());
+        }
+        if self.rooms.contains_key(name) {
+            return Err(format!("room {} already exists", name));
+        }
+        if self.rooms.len() >= self.max_rooms {
+            return Err("the server is at its room limit".into());
+        }
+
+        info!("creating room {}", name);
+        self.rooms.insert(
+            name.to_string(),
+            Room::new(name, self.tick_rate, self.interest.clone()),
+        );
+        Ok(())
+    }
+
+    fn join(&mut self, client: ClientId, name: &str, spectate: bool) -> Result<(), String> {
+        if !self.rooms.contains_key(name) {
+            return Err(format!("room {} doesn't exist", name));
+        }
+
+        self.leave(client);
+        let event = if spectate {
+            RoomEvent::Spectating(client)
+        } else {
+            RoomEvent::Joined(client)
+        };
+        self.rooms.get_mut(name).unwrap().send(event);
+        self.clients.insert(client, name.to_string());
+        Ok(())
+    }
+
+    ///Returns whether the client was in a room
+    fn leave(&mut self, client: ClientId) -> bool {
+        let name = match self.clients.remove(&client) {
+            Some(n) => n,
+            None => return false,
+        };
+
+        if let Some(room) = self.rooms.get_mut(&name) {
+            room.send(RoomEvent::Left(client));
+        }
+        true
+    }
+
+    fn forward(&mut self, client: ClientId, action: NetworkingAction) {
+        let room = self
+            .clients
+            .get(&client)
+            .and_then(|name| self.rooms.get_mut(name));
+        if let Some(room) = room {
+            room.send(RoomEvent::Message(client, action));
+        }
+    }
+
+    fn update(&mut self, delta: f64, net: &Net) {
+        for room in self.rooms.values_mut() {
+            room.update(delta, net);
+        }
+
+        let default_room = &self.default_room;
+        let clients = &self.clients;
+        self.rooms.retain(|name, room| {
+            let keep = name == default_room
+                || room.idle < ROOM_IDLE_SECONDS
+                || clients.values().any(|n| n == name);
+            if !keep {
+                info!("closing empty room {}", name);
+            }
+            keep
+        });
+    }
+}
+
+///Answers a client's hello with a welcome, or drops it with the reason why
+fn handle_hello(
+    net: &Net,
+    auth: &mut Auth,
+    access: &mut AccessList,
+    client: ClientId,
+    credentials: &Credentials,
+) {
+    let admitted = auth
+        .login(client, credentials, net.is_encrypted(client))
+        .and_then(|session| {
+            //without an auth file the claimed name is all there is
+            let claimed = match credentials {
+                Credentials::Secret { name, .. } => Some(name.clone()),
+                _ => None,
+            };
+            let who = Identity {
+                name: auth.name(client).map(String::from).or(claimed),
+                ip: net.addr(client).map(|a| a.ip()),
+            };
+            if let Err(reason) = access.admit(client, who, auth::now()) {
+                auth.logout(client);
+                return Err(reason);
+            }
+            Ok(session)
+        });
+
+    match admitted {
+        Ok(session) => {
+            let welcome = NetworkingAction::Welcome {
+                client_id: client,
+                udp_key: net.udp_key(client).unwrap_or_default(),
+                session,
+            };
+            net.send(client, &welcome);
+        }
+        Err(reason) => {
+            info!("rejected {:?}: {:?}", client, reason);
+            net.send(client, &NetworkingAction::Rejected(reason));
+            net.disconnect(client);
+        }
+    }
+}
+
+fn system_handle_events(
+    net: Res<Net>,
+    mut auth: ResMut<Auth>,
+    mut access: ResMut<AccessList>,
+    mut rooms: ResMut<Rooms>,
+) {
+    for event in net.drain_events() {
+        match event {
+            //nothing happens until the client says hello
+            ServerEvent::Connected => {}
+            ServerEvent::Message(client, action) if !auth.is_accepted(client) => match action {
+                NetworkingAction::Hello(credentials) => {
+                    handle_hello(&net, &mut auth, &mut access, client, &credentials)
+                }
+                NetworkingAction::Heartbeat => {}
+                _ => info!("{:?} sent a packet before its hello", client),
+            },
+            ServerEvent::Message(client, action) => match action {
+                NetworkingAction::ListRooms => {
+                    net.send(client, &NetworkingAction::RoomList(rooms.list()));
+                }
+                NetworkingAction::CreateRoom(name) => {
+                    let reply = match rooms.create(&name) {
+                        Ok(_) => NetworkingAction::RoomList(rooms.list()),
+                        Err(e) => NetworkingAction::RoomError(e),
+                    };
+                    net.send(client, &reply);
+                }
+                NetworkingAction::JoinRoom(name) => {
+                    //RoomJoined comes from the room itself once it spawned the player
+                    if let Err(e) = rooms.join(client, &name, false) {
+                        net.send(client, &NetworkingAction::RoomError(e));
+                    }
+                }
+                NetworkingAction::SpectateRoom(name) => {
+                    if let Err(e) = rooms.join(client, &name, true) {
+                        net.send(client, &NetworkingAction::RoomError(e));
+                    }
+                }
+                NetworkingAction::LeaveRoom => {
+                    if rooms.leave(client) {
+                        net.send(client, &NetworkingAction::RoomLeft);
+                    }
+                }
+                NetworkingAction::Print(s) => info!("{:?} says {}", client, s),
+                NetworkingAction::Heartbeat
+                | NetworkingAction::Hello(_)
+                | NetworkingAction::UdpHello { .. } => {}
+                action => rooms.forward(client, action),
+            },
+            ServerEvent::Disconnected(client) => {
+                rooms.leave(client);
+                auth.logout(client);
+                access.leave(client);
+            }
+        }
+    }
+}
+
+fn system_update_rooms(time: Res<Time>, net: Res<Net>, mut rooms: ResMut<Rooms>) {
+    rooms.update(time.delta_seconds_f64(), &net);
+}
+
+pub fn build(app: &mut App) {
+    let rooms = Rooms::from_config(app.world.resource::<ServerConfig>());
+
+    app.insert_resource(rooms)
+        .add_system_to_stage(CoreStage::PreUpdate, system_handle_events)
+        .add_system(system_update_rooms);
+}
+
+#[test]
+fn room_names() {
+    let mut rooms = Rooms::from_config(&ServerConfig::default());
+    assert!(rooms.create("").is_err());
+    assert!(rooms.create(&"a".repeat(MAX_ROOM_NAME_CHARS + 1)).is_err());
+    assert!(rooms.create("line\nbreak").is_err());
+    assert!(rooms.create("\u{1b}[31mred").is_err());
+    assert!(rooms.create(&"ö".repeat(MAX_ROOM_NAME_CHARS)).is_ok());
+    assert!(rooms.create("fine room").is_ok());
+    assert_eq!(rooms.list().len(), 3);
+}
+
+#[test]
+fn hits() {
+    use shared::enemy::Enemy;
+    use shared::knockback::{Impulse, Knockback};
+    use shared::replication::NetId;
+
+    let interest = InterestSettings {
+        radius: 10.0,
+        hysteresis: 2.0,
+    };
+    let mut room = Room::new("test", 60.0, interest);
+    let (shooter, other, spectator) = (ClientId(1), ClientId(2), ClientId(3));
+    room.send(RoomEvent::Joined(shooter));
+    room.send(RoomEvent::Joined(other));
+    room.send(RoomEvent::Spectating(spectator));
+    let mut spawn_enemy = |x: f32| {
+        room.world
+            .spawn()
+            .insert_bundle((
+                Transform::from_xyz(x, 0.0, 0.0),
+                Enemy::default(),
+                Knockback::default(),
+                Replicated,
+            ))
+            .id()
+    };
+    let (near, far) = (spawn_enemy(5.0), spawn_enemy(50.0));
+    //spawns the players and works out what everyone can see
+    room.schedule.run(&mut room.world);
+
+    let players = room.world.resource::<Players>().0.clone();
+    let id = |room: &Room, entity| *room.world.get::<NetId>(entity).unwrap();
+    let hit = |target| NetworkingAction::Hit {
+        target,
+        impulse: Impulse {
+            velocity: Vec3::X * 100.0,
+            decay: None,
+            control_lock: Some(5.0),
+        },
+    };
+    for (client, target) in [
+        (shooter, id(&room, near)),
+        (shooter, id(&room, far)),
+        (shooter, id(&room, players[&shooter])),
+        (spectator, id(&room, near)),
+    ] {
+        room.send(RoomEvent::Message(client, hit(target)));
+    }
+    room.schedule.run(&mut room.world);
+
+    //cut down to what a hit can do
+    let knockback = *room.world.get::<Knockback>(near).unwrap();
+    assert_eq!(knockback.velocity, Vec3::X * 25.0);
+    assert_eq!(knockback.pending_lock, Some(1.0));
+    assert_eq!(
+        *room.world.get::<Knockback>(far).unwrap(),
+        Knockback::default()
+    );
+    let own = room.world.get::<Knockback>(players[&shooter]).unwrap();
+    assert_eq!(*own, Knockback::default());
+
+    //other players are pushed on their own client
+    let target = id(&room, players[&other]);
+    room.send(RoomEvent::Message(shooter, hit(target)));
+    room.schedule.run(&mut room.world);
+    room.schedule.run(&mut room.world);
+    let outbox = room.world.resource::<Outbox>();
+    let pushed = |client| {
+        outbox
+            .reliable
+            .iter()
+            .any(|(c, a)| *c == client && matches!(a, NetworkingAction::Impulse(_)))
+    };
+    assert!(pushed(other));
+    assert!(!pushed(shooter));
+}
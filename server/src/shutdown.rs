@@ -0,0 +1,109 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+
+use crate::config::ServerConfig;
+use crate::net::Net;
+
+///Set by the signal handler once SIGINT or SIGTERM arrives
+#[derive(Default)]
+pub struct ShutdownSignal(Arc<AtomicBool>);
+
+///Set when the listener can't be brought back, main exits with an error once the app stopped
+static FAILED: AtomicBool = AtomicBool::new(false);
+
+///Runs after the rooms ticked, systems that save state on shutdown go after it
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Supervise;
+
+pub fn failed() -> bool {
+    FAILED.load(Ordering::Relaxed)
+}
+
+pub fn build(app: &mut App) {
+    let signal = ShutdownSignal::default();
+    let handler_signal = signal.0.clone();
+    //the termination feature catches SIGTERM too
+    if let Err(e) = ctrlc::set_handler(move || handler_signal.store(true, Ordering::Relaxed)) {
+        warn!("couldn't install the shutdown handler: {}", e);
+    }
+
+    //last, so rooms finish their tick before clients are told
+    app.insert_resource(signal)
+        .add_system_to_stage(CoreStage::Last, system_supervise.label(Supervise));
+}
+
+///Exits cleanly once asked to, and brings the listener back up if its thread died
+fn system_supervise(
+    signal: Res<ShutdownSignal>,
+    config: Res<ServerConfig>,
+    mut net: ResMut<Net>,
+    mut restarts: Local<u32>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if signal.0.load(Ordering::Relaxed) {
+        info!("shutting down");
+        net.shutdown("the server is shutting down");
+        exit.send(AppExit);
+        return;
+    }
+
+    if !net.is_crashed() {
+        return;
+    }
+
+    if *restarts >= config.listener_restarts {
+        error!("the listener died {} times, giving up", *restarts + 1);
+        return give_up(&net, &mut exit);
+    }
+
+    *restarts += 1;
+    error!(
+        "the listener died, restarting it ({}/{})",
+        *restarts, config.listener_restarts
+    );
+    if let Err(e) = net.restart(&config) {
+        error!("{}", e);
+        give_up(&net, &mut exit);
+    }
+}
+
+fn give_up(net: &Net, exit: &mut EventWriter<AppExit>) {
+    net.shutdown("the server crashed");
+    FAILED.store(true, Ordering::Relaxed);
+    exit.send(AppExit);
+}
+
+#[test]
+fn listener_restarts() {
+    let identity = std::env::temp_dir().join("bdo2_listener_restarts.key");
+    let config = ServerConfig {
+        port: 0,
+        websocket_port: None,
+        listener_restarts: 2,
+        identity_file: identity.to_string_lossy().into(),
+        ..Default::default()
+    };
+
+    let net = crate::net::server(&config).unwrap();
+    let mut app = App::new();
+    app.insert_resource(ShutdownSignal::default())
+        .insert_resource(config)
+        .insert_resource(net)
+        .add_system_to_stage(CoreStage::Last, system_supervise);
+
+    for _ in 0..2 {
+        app.world.resource::<Net>().crash();
+        app.update();
+        assert!(!app.world.resource::<Net>().is_crashed());
+        assert!(app.world.resource::<Events<AppExit>>().is_empty());
+    }
+
+    //out of restarts
+    app.world.resource::<Net>().crash();
+    app.update();
+    assert!(failed());
+    assert!(!app.world.resource::<Events<AppExit>>().is_empty());
+}
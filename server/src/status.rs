@@ -0,0 +1,357 @@
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use serde::Serialize;
+use tiny_http::{Header, Response, Server};
+
+use crate::auth::Auth;
+use crate::config::ServerConfig;
+use crate::rooms::Rooms;
+use crate::shutdown;
+
+///How often the served status is rebuilt
+const REFRESH_SECONDS: f32 = 1.0;
+
+#[derive(Serialize, Default, Clone, Debug, PartialEq)]
+pub struct TickDurations {
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl TickDurations {
+    pub fn from_samples(samples: impl Iterator<Item = Duration>) -> Self {
+        let mut ms: Vec<f64> = samples.map(|d| d.as_secs_f64() * 1000.0).collect();
+        if ms.is_empty() {
+            return Self::default();
+        }
+        ms.sort_by(f64::total_cmp);
+
+        let at = |quantile: f64| ms[((ms.len() - 1) as f64 * quantile).round() as usize];
+        Self {
+            p50_ms: at(0.5),
+            p90_ms: at(0.9),
+            p99_ms: at(0.99),
+            max_ms: ms[ms.len() - 1],
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PlayerStatus {
+    pub client: u64,
+    ///Only known if the server has authentication turned on
+    pub name: Option<String>,
+    pub room: String,
+    pub spectating: bool,
+    pub rtt_ms: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct RoomStatus {
+    pub name: String,
+    pub players: usize,
+    pub spectators: usize,
+    pub entities: usize,
+    pub replicated: usize,
+    pub tick_duration: TickDurations,
+}
+
+///Everything the status endpoint serves, as json on `/status` and for prometheus on `/metrics`
+#[derive(Serialize, Default)]
+pub struct Status {
+    pub uptime_seconds: f64,
+    ///Ticks per second of every room
+    pub tick_rate: f64,
+    ///Over the latest ticks of every room
+    pub tick_duration: TickDurations,
+    pub entities: usize,
+    pub players: Vec<PlayerStatus>,
+    pub rooms: Vec<RoomStatus>,
+}
+
+///Label values are quoted, so room and player names can't break out of them
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl Status {
+    ///The prometheus text format
+    pub fn metrics(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, help: &str, samples: Vec<(String, f64)>| {
+            writeln!(out, "# HELP bdo2_{} {}", name, help).unwrap();
+            writeln!(out, "# TYPE bdo2_{} gauge", name).unwrap();
+            for (labels, value) in samples {
+                writeln!(out, "bdo2_{}{} {}", name, labels, value).unwrap();
+            }
+        };
+
+        metric(
+            "uptime_seconds",
+            "Seconds since the server started.",
+            vec![(String::new(), self.uptime_seconds)],
+        );
+        metric(
+            "tick_rate",
+            "Ticks per second of every room.",
+            vec![(String::new(), self.tick_rate)],
+        );
+        let d = &self.tick_duration;
+        metric(
+            "tick_duration_seconds",
+            "Duration of the latest room ticks.",
+            [
+                (0.5, d.p50_ms),
+                (0.9, d.p90_ms),
+                (0.99, d.p99_ms),
+                (1.0, d.max_ms),
+            ]
+            .into_iter()
+            .map(|(q, ms)| (format!("{{quantile=\"{}\"}}", q), ms / 1000.0))
+            .collect(),
+        );
+        metric(
+            "entities",
+            "Entities in every room.",
+            vec![(String::new(), self.entities as f64)],
+        );
+
+        let room = |name: &str| format!("{{room=\"{}\"}}", escape(name));
+        metric(
+            "room_players",
+            "Players in a room.",
+            self.rooms
+                .iter()
+                .map(|r| (room(&r.name), r.players as f64))
+                .collect(),
+        );
+        metric(
+            "room_spectators",
+            "Spectators in a room.",
+            self.rooms
+                .iter()
+                .map(|r| (room(&r.name), r.spectators as f64))
+                .collect(),
+        );
+        metric(
+            "room_entities",
+            "Entities in a room.",
+            self.rooms
+                .iter()
+                .map(|r| (room(&r.name), r.entities as f64))
+                .collect(),
+        );
+        metric(
+            "player_rtt_seconds",
+            "Smoothed round trip time of a client.",
+            self.players
+                .iter()
+                .filter_map(|p| {
+                    let labels =
+                        format!("{{client=\"{}\",room=\"{}\"}}", p.client, escape(&p.room));
+                    Some((labels, p.rtt_ms? / 1000.0))
+                })
+                .collect(),
+        );
+
+        out
+    }
+}
+
+///The status shared with the http thread, rebuilt once a second
+pub struct StatusPage {
+    started: Instant,
+    status: Arc<Mutex<Status>>,
+    refresh: Timer,
+}
+
+pub fn build(app: &mut App) {
+    let config = app.world.resource::<ServerConfig>();
+    let (addr, stats_file) = (config.status_addr.clone(), config.stats_file.clone());
+    if addr.is_none() && stats_file.is_none() {
+        return;
+    }
+
+    let status: Arc<Mutex<Status>> = Default::default();
+    app.insert_resource(StatusPage {
+        started: Instant::now(),
+        status: status.clone(),
+        refresh: Timer::from_seconds(REFRESH_SECONDS, true),
+    });
+    if stats_file.is_some() {
+        app.add_system_to_stage(
+            CoreStage::Last,
+            system_save_status.after(shutdown::Supervise),
+        );
+    }
+
+    let addr = match addr {
+        Some(a) => a,
+        None => return,
+    };
+    //the game runs fine without it, so a taken port isn't fatal
+    let server = match Server::http(&addr) {
+        Ok(s) => s,
+        Err(e) => return error!("couldn't serve the status page on {}: {}", addr, e),
+    };
+    info!("serving status on http://{}/status", addr);
+
+    std::thread::spawn(move || serve(server, status));
+    app.add_system(system_update_status);
+}
+
+fn serve(server: Server, status: Arc<Mutex<Status>>) {
+    for request in server.incoming_requests() {
+        let path = request
+            .url()
+            .split('?')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        let (body, content_type) = match path.as_str() {
+            "/status" => {
+                let status = status.lock().unwrap();
+                let body = serde_json::to_string(&*status).expect("Status is always serializable");
+                (body, "application/json")
+            }
+            "/metrics" => (
+                status.lock().unwrap().metrics(),
+                "text/plain; version=0.0.4",
+            ),
+            _ => {
+                let _ = request.respond(Response::empty(404));
+                continue;
+            }
+        };
+
+        let header = Header::from_bytes("Content-Type", content_type)
+            .expect("content types are valid headers");
+        if let Err(e) = request.respond(Response::from_string(body).with_header(header)) {
+            info!("failed to answer a status request: {}", e);
+        }
+    }
+}
+
+fn collect(page: &StatusPage, config: &ServerConfig, auth: &Auth, rooms: &mut Rooms) -> Status {
+    let mut status = Status {
+        uptime_seconds: page.started.elapsed().as_secs_f64(),
+        tick_rate: config.room_tick_rate,
+        ..Default::default()
+    };
+
+    let mut all_durations = vec![];
+    for (name, room) in rooms.iter_mut() {
+        let replicated = room.replicated();
+        status
+            .players
+            .extend(
+                room.clients()
+                    .into_iter()
+                    .map(|(client, spectating, rtt)| PlayerStatus {
+                        client: client.0,
+                        name: auth.name(client).map(String::from),
+                        room: name.clone(),
+                        spectating,
+                        rtt_ms: rtt.map(|r| r.as_secs_f64() * 1000.0),
+                    }),
+            );
+
+        let durations = room.tick_durations();
+        all_durations.extend(durations.iter().copied());
+        status.entities += room.entities();
+        status.rooms.push(RoomStatus {
+            name: name.clone(),
+            players: room.players(),
+            spectators: room.spectators(),
+            entities: room.entities(),
+            replicated,
+            tick_duration: TickDurations::from_samples(durations.iter().copied()),
+        });
+    }
+    status.tick_duration = TickDurations::from_samples(all_durations.into_iter());
+    status
+}
+
+fn system_update_status(
+    time: Res<Time>,
+    config: Res<ServerConfig>,
+    auth: Res<Auth>,
+    mut page: ResMut<StatusPage>,
+    mut rooms: ResMut<Rooms>,
+) {
+    if !page.refresh.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let status = collect(&page, &config, &auth, &mut rooms);
+    *page.status.lock().unwrap() = status;
+}
+
+///Writes out the status one last time once the server is stopping
+fn system_save_status(
+    mut exits: EventReader<AppExit>,
+    config: Res<ServerConfig>,
+    auth: Res<Auth>,
+    page: Res<StatusPage>,
+    mut rooms: ResMut<Rooms>,
+) {
+    if exits.iter().next().is_none() {
+        return;
+    }
+    let file = match &config.stats_file {
+        Some(f) => f,
+        None => return,
+    };
+
+    let status = collect(&page, &config, &auth, &mut rooms);
+    let json = serde_json::to_string_pretty(&status).expect("Status is always serializable");
+    match std::fs::write(file, json) {
+        Ok(()) => info!("saved the last status to {}", file),
+        Err(e) => error!("couldn't save the last status to {}: {}", file, e),
+    }
+}
+
+#[test]
+fn metrics_format() {
+    let durations = (1..=100).map(Duration::from_millis);
+    let tick_duration = TickDurations::from_samples(durations);
+    assert_eq!(tick_duration.p50_ms, 51.0);
+    assert_eq!(tick_duration.p99_ms, 99.0);
+    assert_eq!(tick_duration.max_ms, 100.0);
+
+    let status = Status {
+        uptime_seconds: 5.0,
+        tick_rate: 60.0,
+        tick_duration,
+        entities: 3,
+        players: vec![PlayerStatus {
+            client: 1,
+            name: None,
+            room: "default".into(),
+            spectating: false,
+            rtt_ms: Some(20.0),
+        }],
+        rooms: vec![RoomStatus {
+            name: "a \"quoted\" room".into(),
+            players: 1,
+            spectators: 0,
+            entities: 3,
+            replicated: 2,
+            tick_duration: TickDurations::default(),
+        }],
+    };
+
+    let metrics = status.metrics();
+    assert!(metrics.contains("bdo2_uptime_seconds 5\n"));
+    assert!(metrics.contains("bdo2_tick_duration_seconds{quantile=\"0.99\"} 0.099\n"));
+    assert!(metrics.contains("bdo2_room_players{room=\"a \\\"quoted\\\" room\"} 1\n"));
+    assert!(metrics.contains("bdo2_player_rtt_seconds{client=\"1\",room=\"default\"} 0.02\n"));
+}
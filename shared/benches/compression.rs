@@ -0,0 +1,99 @@
+use bevy::prelude::*;
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use shared::net;
+use shared::replication::{ComponentData, ComponentKind, NetId, Replicate, ReplicationMessage};
+use shared::snapshot::{ComponentDelta, EntityDelta, Snapshot};
+use shared::{Health, NetworkingAction, Physics};
+
+///The full snapshot a client gets when it joins a room with this many entities
+fn full_snapshot(entities: u64) -> NetworkingAction {
+    let component = |kind, value| {
+        ComponentDelta::Full(ComponentData {
+            kind: ComponentKind(kind),
+            value,
+        })
+    };
+
+    let entities = (0..entities)
+        .map(|i| {
+            let f = i as f32;
+            let transform =
+                Transform::from_xyz(f * 3.1, 1.0, -f * 1.7).with_rotation(Quat::from_rotation_y(f));
+            let physics = Physics {
+                velocity: Vec3::new(f.sin() * 4.0, 0.0, f.cos() * 4.0),
+                ..Default::default()
+            };
+
+            //kinds in the order of default_registry
+            EntityDelta {
+                id: NetId(i),
+                components: vec![
+                    component(0, serde_json::to_value(transform.extract()).unwrap()),
+                    component(1, serde_json::to_value(physics.extract()).unwrap()),
+                    component(
+                        3,
+                        serde_json::to_value(Health::new(100.0).extract()).unwrap(),
+                    ),
+                ],
+            }
+        })
+        .collect();
+
+    NetworkingAction::Replication(ReplicationMessage::Snapshot(Snapshot {
+        epoch: 1,
+        tick: 1000,
+        baseline: None,
+        entities,
+        removed: vec![],
+    }))
+}
+
+fn bench_compression(c: &mut Criterion) {
+    let messages = [
+        ("heartbeat", NetworkingAction::Heartbeat),
+        ("snapshot of 10", full_snapshot(10)),
+        ("snapshot of 100", full_snapshot(100)),
+        ("snapshot of 1000", full_snapshot(1000)),
+    ];
+
+    //criterion only measures time, the bandwidth is the size of the frames
+    for (name, action) in &messages {
+        let json = serde_json::to_vec(action).unwrap().len();
+        let framed = net::encode(action).len();
+        println!(
+            "{}: {} bytes as json, {} bytes framed ({:.0}%)",
+            name,
+            json,
+            framed,
+            framed as f64 / json as f64 * 100.0
+        );
+    }
+
+    let mut group = c.benchmark_group("encode");
+    for (name, action) in &messages {
+        group.bench_function(format!("json {}", name), |b| {
+            b.iter(|| serde_json::to_vec(action).unwrap())
+        });
+        group.bench_function(format!("framed {}", name), |b| {
+            b.iter(|| net::encode(action))
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("decode");
+    for (name, action) in &messages {
+        let json = serde_json::to_vec(action).unwrap();
+        let framed = net::encode(action);
+        group.bench_function(format!("json {}", name), |b| {
+            b.iter(|| serde_json::from_slice::<NetworkingAction>(&json).unwrap())
+        });
+        group.bench_function(format!("framed {}", name), |b| {
+            b.iter(|| net::decode(&framed).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_compression);
+criterion_main!(benches);
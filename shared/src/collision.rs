@@ -0,0 +1,344 @@
+use bevy::prelude::*;
+
+///Height of the ground plane every map has, below all colliders
+pub const FLOOR: f32 = 0.0;
+///How far below its feet something still counts as standing on it
+pub const GROUND_PROBE: f32 = 0.01;
+///Ramps can be walked up, and the player stays on the ground walking down them, as long as the
+///surface doesn't change height by more than this in one substep
+pub const STEP_HEIGHT: f32 = 0.25;
+
+///Boxes closer than this count as touching, not overlapping. Keeps rounding from snagging the
+///player on a surface it is sliding along.
+const SKIN: f32 = 1e-4;
+///Longest distance moved at once, so fast movement can't skip through thin boxes
+const MAX_SUBSTEP: f32 = 0.1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn from_center(center: Vec3, half_extents: Vec3) -> Self {
+        Self {
+            min: center - half_extents,
+            max: center + half_extents,
+        }
+    }
+
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        (self.min + SKIN).cmplt(other.max).all() && (self.max - SKIN).cmpgt(other.min).all()
+    }
+
+    fn footprint_overlaps(&self, other: &Aabb) -> bool {
+        self.min.x + SKIN < other.max.x
+            && self.max.x - SKIN > other.min.x
+            && self.min.z + SKIN < other.max.z
+            && self.max.z - SKIN > other.min.z
+    }
+
+    fn footprint_contains(&self, x: f32, z: f32) -> bool {
+        (self.min.x..=self.max.x).contains(&x) && (self.min.z..=self.max.z).contains(&z)
+    }
+}
+
+///Static geometry the player can't walk through, centered on the entity's translation
+#[derive(Component, Clone, Copy, Debug)]
+pub enum Collider {
+    Cuboid {
+        half_extents: Vec3,
+    },
+    ///A box cut in half diagonally. The top slopes from the bottom of the box up to its top
+    ///along `rises_towards`, which is one of ±x or ±z as (x, z).
+    Ramp {
+        half_extents: Vec3,
+        rises_towards: Vec2,
+    },
+}
+
+impl Collider {
+    pub fn cube(size: f32) -> Self {
+        Collider::Cuboid {
+            half_extents: Vec3::splat(size / 2.0),
+        }
+    }
+
+    pub fn half_extents(&self) -> Vec3 {
+        match *self {
+            Collider::Cuboid { half_extents } | Collider::Ramp { half_extents, .. } => half_extents,
+        }
+    }
+
+    ///Places the collider in the world
+    pub fn solid(&self, entity: Option<Entity>, translation: Vec3) -> Solid {
+        Solid {
+            entity,
+            bounds: Aabb::from_center(translation, self.half_extents()),
+            rises_towards: match *self {
+                Collider::Cuboid { .. } => None,
+                Collider::Ramp { rises_towards, .. } => Some(rises_towards),
+            },
+        }
+    }
+}
+
+///A collider where it is in the world, what movement collides with
+#[derive(Clone, Copy, Debug)]
+pub struct Solid {
+    pub entity: Option<Entity>,
+    pub bounds: Aabb,
+    ///Set for ramps
+    pub rises_towards: Option<Vec2>,
+}
+
+impl Solid {
+    ///How far the ramp's top rises per unit moved along `rises_towards`
+    fn slope(&self, rises_towards: Vec2) -> f32 {
+        let half = (self.bounds.max - self.bounds.min) / 2.0;
+        half.y / (rises_towards.x.abs() * half.x + rises_towards.y.abs() * half.z)
+    }
+
+    ///Height of the top surface above a point of the footprint
+    fn top(&self, x: f32, z: f32) -> f32 {
+        let rises_towards = match self.rises_towards {
+            Some(r) => r,
+            None => return self.bounds.max.y,
+        };
+        let center = (self.bounds.min + self.bounds.max) / 2.0;
+        let along = Vec2::new(x - center.x, z - center.z).dot(rises_towards);
+        (center.y + along * self.slope(rises_towards)).clamp(self.bounds.min.y, self.bounds.max.y)
+    }
+
+    fn normal(&self) -> Vec3 {
+        match self.rises_towards {
+            Some(r) => {
+                let slope = self.slope(r);
+                Vec3::new(-r.x * slope, 1.0, -r.y * slope).normalize()
+            }
+            None => Vec3::Y,
+        }
+    }
+}
+
+///The box that moves with the player. Its bottom is at the entity's translation, so the
+///translation is where the feet are.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct CollisionShape {
+    pub half_extents: Vec3,
+}
+
+///Fits the player's mesh
+impl Default for CollisionShape {
+    fn default() -> Self {
+        Self {
+            half_extents: Vec3::new(0.85, 2.0, 0.7),
+        }
+    }
+}
+
+impl CollisionShape {
+    pub fn aabb(&self, feet: Vec3) -> Aabb {
+        Aabb::from_center(feet + Vec3::Y * self.half_extents.y, self.half_extents)
+    }
+}
+
+///What an entity is standing on, only there while it is on the ground
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct Grounded {
+    pub normal: Vec3,
+    ///None for the floor
+    pub entity: Option<Entity>,
+    pub height: f32,
+}
+
+///Casts down from the feet, finding the highest surface at most `reach` below them. Ramps are
+///only stood on by the middle of the shape, boxes by any part of it.
+pub fn ground(
+    feet: Vec3,
+    shape: &CollisionShape,
+    solids: &[Solid],
+    reach: f32,
+) -> Option<Grounded> {
+    let body = shape.aabb(feet);
+    let reaches = |height: f32| height <= feet.y + SKIN && height >= feet.y - reach;
+
+    let mut found = None;
+    if reaches(FLOOR) {
+        found = Some(Grounded {
+            normal: Vec3::Y,
+            entity: None,
+            height: FLOOR,
+        });
+    }
+
+    for solid in solids {
+        let height = match solid.rises_towards {
+            None if body.footprint_overlaps(&solid.bounds) => solid.bounds.max.y,
+            Some(_) if solid.bounds.footprint_contains(feet.x, feet.z) => solid.top(feet.x, feet.z),
+            _ => continue,
+        };
+        if reaches(height) && found.map(|g: Grounded| height > g.height).unwrap_or(true) {
+            found = Some(Grounded {
+                normal: solid.normal(),
+                entity: solid.entity,
+                height,
+            });
+        }
+    }
+    found
+}
+
+///Moves one axis at a time and pushes the shape back out of anything it ran into, so motion
+///into a surface is dropped and motion along it is kept. Walking into a ramp goes up it. Returns
+///the axes that were blocked.
+pub fn move_and_slide(
+    feet: &mut Vec3,
+    shape: &CollisionShape,
+    motion: Vec3,
+    solids: &[Solid],
+) -> BVec3 {
+    let substeps = (motion.abs().max_element() / MAX_SUBSTEP).ceil().max(1.0);
+    let motion = motion / substeps;
+    let mut blocked = [false; 3];
+
+    for _ in 0..substeps as u32 {
+        //vertical first, so landing on a box happens before sliding across it
+        for axis in [1, 0, 2] {
+            if motion[axis] == 0.0 || blocked[axis] {
+                continue;
+            }
+            feet[axis] += motion[axis];
+
+            for solid in solids {
+                let body = shape.aabb(*feet);
+                if !body.overlaps(&solid.bounds) {
+                    continue;
+                }
+
+                if solid.rises_towards.is_none() {
+                    //low enough to step onto, like where a ramp meets a platform
+                    if axis != 1 && solid.bounds.max.y - feet.y <= STEP_HEIGHT {
+                        feet.y = solid.bounds.max.y;
+                        continue;
+                    }
+                    feet[axis] += if motion[axis] > 0.0 {
+                        solid.bounds.min[axis] - body.max[axis]
+                    } else {
+                        solid.bounds.max[axis] - body.min[axis]
+                    };
+                    blocked[axis] = true;
+                    continue;
+                }
+
+                //ramps are only solid below their top, under the middle of the shape
+                if !solid.bounds.footprint_contains(feet.x, feet.z) {
+                    continue;
+                }
+                let top = solid.top(feet.x, feet.z);
+                if feet.y >= top - SKIN {
+                    continue;
+                }
+
+                if axis != 1 && top - feet.y > STEP_HEIGHT {
+                    //the tall end of the ramp is a wall
+                    feet[axis] -= motion[axis];
+                    blocked[axis] = true;
+                } else if axis == 1 && motion.y > 0.0 && feet.y < solid.bounds.min.y {
+                    //head hit the bottom
+                    feet.y += solid.bounds.min.y - body.max.y;
+                    blocked[1] = true;
+                } else {
+                    feet.y = top;
+                    blocked[1] |= axis == 1;
+                }
+            }
+
+            if axis == 1 && feet.y < FLOOR {
+                feet.y = FLOOR;
+                blocked[1] = true;
+            }
+        }
+    }
+
+    BVec3::new(blocked[0], blocked[1], blocked[2])
+}
+
+#[test]
+fn slide_and_stand() {
+    let shape = CollisionShape {
+        half_extents: Vec3::new(0.5, 1.0, 0.5),
+    };
+    let wall = Collider::Cuboid {
+        half_extents: Vec3::new(0.5, 2.0, 5.0),
+    }
+    .solid(None, Vec3::new(2.0, 2.0, 0.0));
+    let crate_top = Collider::cube(1.0).solid(None, Vec3::new(-3.0, 0.5, 0.0));
+    let solids = [wall, crate_top];
+
+    //walking diagonally into the wall keeps the motion along it
+    let mut feet = Vec3::ZERO;
+    let blocked = move_and_slide(&mut feet, &shape, Vec3::new(3.0, 0.0, 2.0), &solids);
+    assert_eq!(blocked, BVec3::new(true, false, false));
+    assert!((feet.x - 1.0).abs() < 1e-4, "{:?}", feet);
+    assert!((feet.z - 2.0).abs() < 1e-4, "{:?}", feet);
+
+    //falling onto the crate stops on its top, and walking off it works
+    let mut feet = Vec3::new(-3.0, 3.0, 0.0);
+    let blocked = move_and_slide(&mut feet, &shape, Vec3::new(0.0, -5.0, 0.0), &solids);
+    assert!(blocked.y);
+    assert!((feet.y - 1.0).abs() < 1e-4, "{:?}", feet);
+    assert!(ground(feet, &shape, &solids, GROUND_PROBE).is_some());
+
+    let blocked = move_and_slide(&mut feet, &shape, Vec3::new(-2.0, 0.0, 0.0), &solids);
+    assert_eq!(blocked, BVec3::new(false, false, false));
+    assert!(ground(feet, &shape, &solids, GROUND_PROBE).is_none());
+
+    //fast enough to skip over a thin box in one step
+    let thin = Collider::cube(0.2).solid(None, Vec3::new(0.0, 0.5, 5.0));
+    let mut feet = Vec3::new(0.0, 0.0, 3.0);
+    move_and_slide(&mut feet, &shape, Vec3::new(0.0, 0.0, 4.0), &[thin]);
+    assert!(feet.z < 5.0, "went through {:?}", feet);
+}
+
+#[test]
+fn ramps() {
+    let shape = CollisionShape {
+        half_extents: Vec3::new(0.5, 1.0, 0.5),
+    };
+    //rises 2 over 4, from x = 0 to x = 4
+    let ramp = Collider::Ramp {
+        half_extents: Vec3::new(2.0, 1.0, 2.0),
+        rises_towards: Vec2::X,
+    }
+    .solid(None, Vec3::new(2.0, 1.0, 0.0));
+
+    let mut feet = Vec3::new(-1.0, 0.0, 0.0);
+    move_and_slide(&mut feet, &shape, Vec3::new(3.0, 0.0, 0.0), &[ramp]);
+    assert!((feet.y - 1.0).abs() < 1e-4, "{:?}", feet);
+
+    let ground = ground(feet, &shape, &[ramp], GROUND_PROBE).unwrap();
+    assert!((ground.normal - Vec3::new(-1.0, 2.0, 0.0).normalize()).length() < 1e-4);
+
+    //the high end is a wall
+    let mut feet = Vec3::new(5.0, 0.0, 0.0);
+    let blocked = move_and_slide(&mut feet, &shape, Vec3::new(-2.0, 0.0, 0.0), &[ramp]);
+    assert!(blocked.x);
+    assert!(feet.x >= 4.0, "{:?}", feet);
+
+    //and walking up it leads onto a platform as high as its top
+    let platform = Collider::Cuboid {
+        half_extents: Vec3::new(2.0, 1.0, 2.0),
+    }
+    .solid(None, Vec3::new(6.0, 1.0, 0.0));
+    let mut feet = Vec3::new(-1.0, 0.0, 0.0);
+    let blocked = move_and_slide(
+        &mut feet,
+        &shape,
+        Vec3::new(7.0, 0.0, 0.0),
+        &[ramp, platform],
+    );
+    assert!(!blocked.x);
+    assert_eq!(feet.y, 2.0);
+}
@@ -0,0 +1,391 @@
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, ReusableSecret, SharedSecret, StaticSecret};
+
+///First byte of an encrypted frame, plaintext frames are json and never start with it
+pub const SEALED: u8 = 0;
+///First byte of an encrypted udp hello. Udp has no connection, so until it's bound the frame
+///says whose keys it was sealed with.
+pub const SEALED_UDP_HELLO: u8 = 1;
+
+///How far behind the newest counter a message may arrive, udp reorders
+const REPLAY_WINDOW: u64 = 64;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CryptoError {
+    Malformed,
+    ///The counter was seen already or is too far behind
+    Replayed,
+    ///Failed authentication, someone changed it or it's from another session
+    Forged,
+    ///The other side sent a key that doesn't contribute to the shared secret
+    WeakKey,
+}
+
+enum Role {
+    Client,
+    Server,
+}
+
+///The server's long term key. Clients that know its public half can tell the real server from
+///someone sitting in between, only the holder of the secret half ends up with the same session
+///keys.
+pub struct Identity {
+    secret: StaticSecret,
+    public: PublicKey,
+}
+
+impl Identity {
+    pub fn generate() -> Self {
+        Self::from_bytes(StaticSecret::random_from_rng(OsRng).to_bytes())
+    }
+
+    pub fn from_bytes(secret: [u8; 32]) -> Self {
+        let secret = StaticSecret::from(secret);
+        let public = PublicKey::from(&secret);
+        Self { secret, public }
+    }
+
+    ///The secret half, to save it
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.secret.to_bytes()
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        self.public.to_bytes()
+    }
+}
+
+///Our half of the key exchange, the public key is sent in the clear. Fresh for every session.
+pub struct Handshake {
+    secret: ReusableSecret,
+    public: PublicKey,
+}
+
+impl Default for Handshake {
+    fn default() -> Self {
+        let secret = ReusableSecret::random_from_rng(OsRng);
+        let public = PublicKey::from(&secret);
+        Self { secret, public }
+    }
+}
+
+impl Handshake {
+    pub fn public_key(&self) -> [u8; 32] {
+        self.public.to_bytes()
+    }
+
+    ///`server` is the public key of the server's [`Identity`]. It should come from the player's
+    ///config rather than the server, a key taken from the connection only keeps out passive
+    ///eavesdroppers.
+    pub fn finish_client(self, theirs: [u8; 32], server: [u8; 32]) -> Result<Session, CryptoError> {
+        let ephemeral = self.secret.diffie_hellman(&PublicKey::from(theirs));
+        let identity = self.secret.diffie_hellman(&PublicKey::from(server));
+        self.derive(Role::Client, theirs, server, [ephemeral, identity])
+    }
+
+    pub fn finish_server(
+        self,
+        theirs: [u8; 32],
+        identity: &Identity,
+    ) -> Result<Session, CryptoError> {
+        let theirs_public = PublicKey::from(theirs);
+        let ephemeral = self.secret.diffie_hellman(&theirs_public);
+        let static_shared = identity.secret.diffie_hellman(&theirs_public);
+        self.derive(
+            Role::Server,
+            theirs,
+            identity.public_key(),
+            [ephemeral, static_shared],
+        )
+    }
+
+    ///Derives the keys of every channel and direction from both shared secrets
+    fn derive(
+        self,
+        role: Role,
+        theirs: [u8; 32],
+        server: [u8; 32],
+        shared: [SharedSecret; 2],
+    ) -> Result<Session, CryptoError> {
+        if !shared.iter().all(|s| s.was_contributory()) {
+            return Err(CryptoError::WeakKey);
+        }
+
+        let ours = self.public.to_bytes();
+        let (client_key, server_key) = match role {
+            Role::Client => (ours, theirs),
+            Role::Server => (theirs, ours),
+        };
+        let salt = [client_key, server_key, server].concat();
+        let secret = [&shared[0].as_bytes()[..], &shared[1].as_bytes()[..]].concat();
+        let hkdf = Hkdf::<Sha256>::new(Some(&salt), &secret);
+        let key = |label: &str| {
+            let mut key = [0; 32];
+            hkdf.expand(label.as_bytes(), &mut key)
+                .expect("32 bytes is a valid hkdf output length");
+            ChaCha20Poly1305::new(Key::from_slice(&key))
+        };
+
+        let channel = |name: &str| {
+            let from_client = key(&format!("bdo2 {} client", name));
+            let from_server = key(&format!("bdo2 {} server", name));
+            match role {
+                Role::Client => Channel::new(from_client, from_server),
+                Role::Server => Channel::new(from_server, from_client),
+            }
+        };
+
+        Ok(Session {
+            tcp: channel("tcp"),
+            udp: channel("udp"),
+        })
+    }
+}
+
+///Remembers which recent counters were received
+#[derive(Default)]
+struct ReplayWindow {
+    ///One past the highest counter seen
+    next: u64,
+    ///Bit `i` is set if `next - 1 - i` was seen
+    seen: u64,
+}
+
+impl ReplayWindow {
+    fn accepts(&self, counter: u64) -> bool {
+        if counter >= self.next {
+            return true;
+        }
+        let age = self.next - 1 - counter;
+        age < REPLAY_WINDOW && self.seen & (1 << age) == 0
+    }
+
+    fn mark(&mut self, counter: u64) {
+        if counter >= self.next {
+            let shift = counter - self.next + 1;
+            self.seen = if shift >= REPLAY_WINDOW {
+                0
+            } else {
+                self.seen << shift
+            };
+            self.seen |= 1;
+            self.next = counter + 1;
+        } else {
+            self.seen |= 1 << (self.next - 1 - counter);
+        }
+    }
+}
+
+fn nonce(counter: u64) -> [u8; 12] {
+    let mut nonce = [0; 12];
+    nonce[4..].copy_from_slice(&counter.to_le_bytes());
+    nonce
+}
+
+///One direction pair of one transport. Every message gets the next counter as its nonce, so a
+///key never sees the same nonce twice.
+pub struct Channel {
+    send: ChaCha20Poly1305,
+    counter: u64,
+    receive: ChaCha20Poly1305,
+    window: ReplayWindow,
+}
+
+impl Channel {
+    fn new(send: ChaCha20Poly1305, receive: ChaCha20Poly1305) -> Self {
+        Self {
+            send,
+            counter: 0,
+            receive,
+            window: ReplayWindow::default(),
+        }
+    }
+
+    fn seal_body(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let counter = self.counter;
+        self.counter += 1;
+
+        let ciphertext = self
+            .send
+            .encrypt(Nonce::from_slice(&nonce(counter)), plaintext)
+            .expect("messages are far below the chacha20poly1305 size limit");
+        [&counter.to_le_bytes()[..], &ciphertext].concat()
+    }
+
+    fn open_body(&mut self, body: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        if body.len() < 8 {
+            return Err(CryptoError::Malformed);
+        }
+        let (counter, ciphertext) = body.split_at(8);
+        let counter = u64::from_le_bytes(counter.try_into().unwrap());
+
+        if !self.window.accepts(counter) {
+            return Err(CryptoError::Replayed);
+        }
+        let plaintext = self
+            .receive
+            .decrypt(Nonce::from_slice(&nonce(counter)), ciphertext)
+            .map_err(|_| CryptoError::Forged)?;
+        //only authentic messages move the window, or anyone could push it forward
+        self.window.mark(counter);
+
+        Ok(plaintext)
+    }
+
+    pub fn seal(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        [&[SEALED][..], &self.seal_body(plaintext)].concat()
+    }
+
+    pub fn open(&mut self, frame: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        match frame.split_first() {
+            Some((&SEALED, body)) => self.open_body(body),
+            _ => Err(CryptoError::Malformed),
+        }
+    }
+
+    pub fn seal_udp_hello(&mut self, client: u64, plaintext: &[u8]) -> Vec<u8> {
+        let body = self.seal_body(plaintext);
+        [&[SEALED_UDP_HELLO][..], &client.to_le_bytes(), &body].concat()
+    }
+
+    pub fn open_udp_hello(&mut self, frame: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        match frame.split_first() {
+            Some((&SEALED_UDP_HELLO, rest)) if rest.len() >= 8 => self.open_body(&rest[8..]),
+            _ => Err(CryptoError::Malformed),
+        }
+    }
+}
+
+///The client a sealed udp hello claims to be from, it only counts if it opens with their keys
+pub fn udp_hello_sender(frame: &[u8]) -> Option<u64> {
+    match frame.split_first() {
+        Some((&SEALED_UDP_HELLO, rest)) if rest.len() >= 8 => {
+            Some(u64::from_le_bytes(rest[..8].try_into().unwrap()))
+        }
+        _ => None,
+    }
+}
+
+///Keys are shared as hex in config files
+pub fn key_to_hex(key: &[u8; 32]) -> String {
+    key.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn key_from_hex(hex: &str) -> Option<[u8; 32]> {
+    let hex = hex.trim();
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut key = [0; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(key)
+}
+
+///The keys of an encrypted connection, tcp and udp are sealed separately
+pub struct Session {
+    pub tcp: Channel,
+    pub udp: Channel,
+}
+
+#[test]
+fn replay_window() {
+    let mut window = ReplayWindow::default();
+    for counter in [0, 2, 1, 70, 10] {
+        assert!(window.accepts(counter));
+        window.mark(counter);
+    }
+    assert!(!window.accepts(70));
+    assert!(!window.accepts(10));
+    //more than the window behind the newest
+    assert!(!window.accepts(5));
+    assert!(window.accepts(69));
+}
+
+#[test]
+fn loopback_session() {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream, UdpSocket};
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client_tcp = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (mut server_tcp, _) = listener.accept().unwrap();
+
+    let client_udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server_udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client_udp
+        .connect(server_udp.local_addr().unwrap())
+        .unwrap();
+
+    //public keys go over tcp in the clear, the client knows the server's identity already
+    let identity = Identity::generate();
+    let pinned = key_from_hex(&key_to_hex(&identity.public_key())).unwrap();
+    let (client_hs, server_hs) = (Handshake::default(), Handshake::default());
+    client_tcp.write_all(&client_hs.public_key()).unwrap();
+    let mut key = [0; 32];
+    server_tcp.read_exact(&mut key).unwrap();
+    server_tcp.write_all(&server_hs.public_key()).unwrap();
+    let mut server = server_hs.finish_server(key, &identity).unwrap();
+    server_tcp.write_all(&server.tcp.seal(b"welcome")).unwrap();
+
+    let mut key = [0; 32];
+    client_tcp.read_exact(&mut key).unwrap();
+    let mut client = client_hs.finish_client(key, pinned).unwrap();
+
+    let mut frame = [0; 1 + 8 + 7 + 16];
+    client_tcp.read_exact(&mut frame).unwrap();
+    assert_eq!(client.tcp.open(&frame).unwrap(), b"welcome");
+
+    //the udp hello names the client so the server knows which keys to try
+    client_udp
+        .send(&client.udp.seal_udp_hello(7, b"hello"))
+        .unwrap();
+    let mut buf = [0; 1500];
+    let len = server_udp.recv(&mut buf).unwrap();
+    assert_eq!(udp_hello_sender(&buf[..len]), Some(7));
+    assert_eq!(server.udp.open_udp_hello(&buf[..len]).unwrap(), b"hello");
+
+    let first = client.udp.seal(b"first");
+    let second = client.udp.seal(b"second");
+    let mut tampered = client.udp.seal(b"third");
+    *tampered.last_mut().unwrap() ^= 1;
+    for datagram in [&second, &first, &first, &tampered] {
+        client_udp.send(datagram).unwrap();
+    }
+
+    let mut received = vec![];
+    for _ in 0..4 {
+        let len = server_udp.recv(&mut buf).unwrap();
+        received.push(server.udp.open(&buf[..len]));
+    }
+    assert_eq!(
+        received,
+        vec![
+            Ok(b"second".to_vec()),
+            //reordered is fine, a copy is not
+            Ok(b"first".to_vec()),
+            Err(CryptoError::Replayed),
+            Err(CryptoError::Forged),
+        ]
+    );
+
+    //each direction has its own key, a frame can't be reflected back
+    let reflected = server.tcp.seal(b"to client");
+    assert_eq!(server.tcp.open(&reflected), Err(CryptoError::Forged));
+
+    //someone in between can answer the key exchange, but without the identity's secret the
+    //keys don't match
+    let (client_hs, impostor_hs) = (Handshake::default(), Handshake::default());
+    let (client_key, impostor_key) = (client_hs.public_key(), impostor_hs.public_key());
+    let mut impostor = impostor_hs
+        .finish_server(client_key, &Identity::generate())
+        .unwrap();
+    let mut client = client_hs.finish_client(impostor_key, pinned).unwrap();
+    let frame = impostor.tcp.seal(b"welcome");
+    assert_eq!(client.tcp.open(&frame), Err(CryptoError::Forged));
+}
@@ -0,0 +1,85 @@
+use bevy::prelude::*;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::knockback::Knockback;
+
+///Pulls enemies pushed upwards back down to the floor
+const GRAVITY: f32 = 30.0;
+
+#[derive(Component, Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct Enemy {
+    pub facing: f32,
+    pub facing_vel: f32,
+    ///Seconds left of a push's control lock, it doesn't wander until then
+    pub locked_for: f32,
+}
+
+impl Default for Enemy {
+    fn default() -> Self {
+        Self {
+            facing: 0.0,
+            facing_vel: 0.0,
+            locked_for: 0.0,
+        }
+    }
+}
+
+///Random walk used by both the client (offline) and the server
+pub fn wander(enemy: &mut Enemy, trans: &mut Transform, dt: f32) {
+    if enemy.locked_for > 0.0 {
+        enemy.locked_for = (enemy.locked_for - dt).max(0.0);
+        return;
+    }
+
+    trans.rotation = Quat::from_rotation_y(enemy.facing);
+    enemy.facing += 1.0 * dt * enemy.facing_vel;
+    enemy.facing_vel += 1.0 * dt * thread_rng().gen_range(-1.0..1.0);
+
+    let (x, z) = enemy.facing.sin_cos();
+
+    trans.translation += Vec3::new(x, 0.0, z) * dt * 20.0;
+}
+
+///Moves an enemy along with the pushes it got. Enemies have no movement state, so a control lock
+///stops them wandering instead, and an upwards push falls back to the floor they walk on.
+pub fn knocked_back(enemy: &mut Enemy, knockback: &mut Knockback, trans: &mut Transform, dt: f32) {
+    if let Some(lock) = knockback.pending_lock.take() {
+        enemy.locked_for = enemy.locked_for.max(lock as f32);
+    }
+
+    let grounded = trans.translation.y <= 0.0 && knockback.velocity.y <= 0.0;
+    if !grounded {
+        knockback.velocity.y -= GRAVITY * dt;
+    }
+    trans.translation += knockback.advance(grounded, dt);
+    if trans.translation.y < 0.0 {
+        trans.translation.y = 0.0;
+        knockback.velocity.y = 0.0;
+    }
+}
+
+#[test]
+fn knocked_up() {
+    let mut enemy = Enemy::default();
+    let mut trans = Transform::default();
+    let mut knockback = Knockback::default();
+    knockback.add(&crate::knockback::Impulse {
+        velocity: Vec3::new(5.0, 10.0, 0.0),
+        decay: None,
+        control_lock: Some(0.5),
+    });
+
+    let mut path = vec![];
+    for _ in 0..60 {
+        knocked_back(&mut enemy, &mut knockback, &mut trans, 1.0 / 60.0);
+        wander(&mut enemy, &mut trans, 1.0 / 60.0);
+        path.push(trans.translation);
+    }
+
+    //went up and came back down, and only started wandering off once the lock was over
+    assert!(path[10].y > 1.0);
+    assert_eq!(path.last().unwrap().y, 0.0);
+    assert!(path[..29].iter().all(|p| p.z == 0.0));
+    assert!(path[59].z > 0.0);
+}
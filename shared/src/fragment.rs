@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+///First byte of a fragment. Whole frames are json or sealed, see [`crate::crypto`].
+pub const FRAGMENT: u8 = 2;
+
+///Largest datagram we send, leaves room for ip and udp headers inside common MTUs
+pub const MAX_DATAGRAM: usize = 1200;
+
+///marker, group id, index, count
+const HEADER: usize = 1 + 2 + 1 + 1;
+const MAX_PAYLOAD: usize = MAX_DATAGRAM - HEADER;
+const MAX_FRAGMENTS: usize = u8::MAX as usize;
+
+///A group that isn't complete after this long lost a fragment and is dropped
+pub const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(1);
+///Groups being reassembled at once, the oldest is dropped to make room
+const MAX_GROUPS: usize = 32;
+
+///Splits frames that don't fit in one datagram
+#[derive(Default)]
+pub struct Fragmenter {
+    next_group: u16,
+}
+
+impl Fragmenter {
+    ///Returns the datagrams to send, just the frame itself if it fits. `None` if the frame is
+    ///too big to ever be reassembled.
+    pub fn split(&mut self, frame: Vec<u8>) -> Option<Vec<Vec<u8>>> {
+        if frame.len() <= MAX_DATAGRAM {
+            return Some(vec![frame]);
+        }
+
+        let count = frame.chunks(MAX_PAYLOAD).len();
+        if count > MAX_FRAGMENTS {
+            return None;
+        }
+
+        let group = self.next_group;
+        self.next_group = self.next_group.wrapping_add(1);
+
+        let fragments = frame
+            .chunks(MAX_PAYLOAD)
+            .enumerate()
+            .map(|(index, payload)| {
+                let mut fragment = Vec::with_capacity(HEADER + payload.len());
+                fragment.push(FRAGMENT);
+                fragment.extend_from_slice(&group.to_le_bytes());
+                fragment.push(index as u8);
+                fragment.push(count as u8);
+                fragment.extend_from_slice(payload);
+                fragment
+            })
+            .collect();
+        Some(fragments)
+    }
+}
+
+struct Group {
+    started: Instant,
+    parts: Vec<Option<Vec<u8>>>,
+    received: usize,
+}
+
+///Collects fragments from one sender until their frame is complete
+#[derive(Default)]
+pub struct Reassembler {
+    groups: HashMap<u16, Group>,
+}
+
+impl Reassembler {
+    ///Returns a frame once all of its fragments arrived, datagrams that aren't fragments are
+    ///returned as they are
+    pub fn receive(&mut self, datagram: &[u8], now: Instant) -> Option<Vec<u8>> {
+        if datagram.first() != Some(&FRAGMENT) {
+            return Some(datagram.to_vec());
+        }
+        if datagram.len() <= HEADER {
+            return None;
+        }
+
+        self.expire(now);
+
+        let group_id = u16::from_le_bytes([datagram[1], datagram[2]]);
+        let (index, count) = (datagram[3] as usize, datagram[4] as usize);
+        if index >= count {
+            return None;
+        }
+
+        if !self.groups.contains_key(&group_id) && self.groups.len() >= MAX_GROUPS {
+            let oldest = self
+                .groups
+                .iter()
+                .min_by_key(|(_, g)| g.started)
+                .map(|(id, _)| *id);
+            if let Some(oldest) = oldest {
+                self.groups.remove(&oldest);
+            }
+        }
+
+        let group = self.groups.entry(group_id).or_insert_with(|| Group {
+            started: now,
+            parts: vec![None; count],
+            received: 0,
+        });
+        //a leftover group with the same id after wrapping around
+        if group.parts.len() != count {
+            return None;
+        }
+
+        if group.parts[index].is_none() {
+            group.parts[index] = Some(datagram[HEADER..].to_vec());
+            group.received += 1;
+        }
+        if group.received < count {
+            return None;
+        }
+
+        let group = self.groups.remove(&group_id)?;
+        Some(group.parts.into_iter().flatten().flatten().collect())
+    }
+
+    ///Drops every group that didn't complete in time
+    pub fn expire(&mut self, now: Instant) {
+        self.groups
+            .retain(|_, g| now.duration_since(g.started) < REASSEMBLY_TIMEOUT);
+    }
+}
+
+#[test]
+fn reorder_and_loss() {
+    let frame: Vec<u8> = (0..5000).map(|i| (i % 251) as u8).collect();
+    let mut fragmenter = Fragmenter::default();
+    let mut reassembler = Reassembler::default();
+    let start = Instant::now();
+
+    //small frames go as they are
+    assert_eq!(fragmenter.split(vec![1, 2, 3]), Some(vec![vec![1, 2, 3]]));
+    assert_eq!(reassembler.receive(&[1, 2, 3], start), Some(vec![1, 2, 3]));
+
+    let mut fragments = fragmenter.split(frame.clone()).unwrap();
+    assert_eq!(fragments.len(), 5);
+    assert!(fragments.iter().all(|f| f.len() <= MAX_DATAGRAM));
+
+    //reordered, with a duplicate
+    fragments.reverse();
+    fragments.insert(2, fragments[1].clone());
+    let received: Vec<_> = fragments
+        .iter()
+        .filter_map(|f| reassembler.receive(f, start))
+        .collect();
+    assert_eq!(received, vec![frame.clone()]);
+
+    //one fragment lost, the group never completes and times out
+    let lost = fragmenter.split(frame.clone()).unwrap();
+    for fragment in lost.iter().skip(1) {
+        assert_eq!(reassembler.receive(fragment, start), None);
+    }
+    let later = start + REASSEMBLY_TIMEOUT;
+    reassembler.expire(later);
+    assert!(reassembler.groups.is_empty());
+    assert_eq!(reassembler.receive(&lost[0], later), None);
+
+    //the next group still gets through
+    let next = fragmenter.split(frame.clone()).unwrap();
+    let received: Vec<_> = next
+        .iter()
+        .filter_map(|f| reassembler.receive(f, later))
+        .collect();
+    assert_eq!(received, vec![frame]);
+
+    assert_eq!(fragmenter.split(vec![0; MAX_PAYLOAD * 256]), None);
+}
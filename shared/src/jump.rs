@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+///A value over time, in seconds
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum Curve {
+    Constant(f32),
+    ///`[seconds, value]` points in time order, linear in between and flat past either end
+    Points(Vec<[f32; 2]>),
+    ///`offset + scale * (t - shift)^exponent`, but never above `max`
+    Power {
+        offset: f32,
+        scale: f32,
+        shift: f32,
+        exponent: f32,
+        max: f32,
+    },
+}
+
+impl Curve {
+    pub fn sample(&self, t: f32) -> f32 {
+        match self {
+            Curve::Constant(v) => *v,
+            Curve::Points(points) => {
+                let after = points.iter().position(|p| p[0] > t);
+                match after {
+                    None => points.last().map(|p| p[1]).unwrap_or(0.0),
+                    Some(0) => points[0][1],
+                    Some(i) => {
+                        let ([t0, v0], [t1, v1]) = (points[i - 1], points[i]);
+                        v0 + (v1 - v0) * (t - t0) / (t1 - t0)
+                    }
+                }
+            }
+            Curve::Power {
+                offset,
+                scale,
+                shift,
+                exponent,
+                max,
+            } => (offset + scale * (t - shift).powf(*exponent)).min(*max),
+        }
+    }
+}
+
+///How an entity jumps and falls. Loaded from yaml so it can be tuned without rebuilding, and
+///serializable so the server can be given the same numbers.
+#[derive(Component, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct JumpProfile {
+    ///Upwards speed a jump starts with
+    pub jump_velocity: f32,
+    ///Gravity while going up, over seconds since the jump
+    pub rising: Curve,
+    ///Gravity while going down, over seconds since the entity started falling. Stronger than
+    ///`rising` for a snappier fall.
+    pub falling: Curve,
+    ///Gravity is multiplied by `apex_gravity_scale` while the vertical speed is below this,
+    ///under 1 for hang time at the top of a jump
+    #[serde(default)]
+    pub apex_speed: f32,
+    #[serde(default = "one")]
+    pub apex_gravity_scale: f32,
+    ///Falling never gets faster than this
+    pub max_fall_speed: Option<f32>,
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+impl Default for JumpProfile {
+    fn default() -> Self {
+        Self {
+            jump_velocity: 15.0,
+            rising: Curve::Constant(30.0),
+            falling: Curve::Constant(30.0),
+            apex_speed: 0.0,
+            apex_gravity_scale: 1.0,
+            max_fall_speed: None,
+        }
+    }
+}
+
+impl JumpProfile {
+    ///Downwards acceleration for the current vertical speed
+    pub fn gravity(&self, vertical_speed: f32, since_jump: f32, since_falling: f32) -> f32 {
+        let gravity = if vertical_speed > 0.0 {
+            self.rising.sample(since_jump)
+        } else {
+            self.falling.sample(since_falling)
+        };
+
+        if vertical_speed.abs() < self.apex_speed {
+            gravity * self.apex_gravity_scale
+        } else {
+            gravity
+        }
+    }
+}
+
+///Named profiles, as written in the profiles file
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct JumpProfiles(pub HashMap<String, JumpProfile>);
+
+impl JumpProfiles {
+    pub fn from_yaml(yaml: &str) -> Result<Self, serde_yaml::Error> {
+        serde_yaml::from_str(yaml)
+    }
+
+    ///The named profile, or the default one if the file doesn't have it
+    pub fn get(&self, name: &str) -> JumpProfile {
+        self.0.get(name).cloned().unwrap_or_else(|| {
+            warn!("No jump profile named {}, using the default", name);
+            JumpProfile::default()
+        })
+    }
+}
+
+#[test]
+fn curves() {
+    let points = Curve::Points(vec![[0.0, 10.0], [1.0, 30.0], [2.0, 30.0]]);
+    assert_eq!(points.sample(-1.0), 10.0);
+    assert_eq!(points.sample(0.5), 20.0);
+    assert_eq!(points.sample(5.0), 30.0);
+
+    let power = Curve::Power {
+        offset: 25.0,
+        scale: 1.0,
+        shift: 0.5,
+        exponent: 2.0,
+        max: 35.0,
+    };
+    assert_eq!(power.sample(0.5), 25.0);
+    assert_eq!(power.sample(10.0), 35.0);
+
+    let profiles = JumpProfiles::from_yaml(
+        "
+player:
+  jump_velocity: 12.0
+  rising: !Constant 20.0
+  falling: !Points [[0.0, 20.0], [0.5, 60.0]]
+  apex_speed: 2.0
+  apex_gravity_scale: 0.5
+  max_fall_speed: 40.0
+",
+    )
+    .unwrap();
+    let player = profiles.get("player");
+    assert_eq!(player.gravity(10.0, 0.1, 0.0), 20.0);
+    assert_eq!(player.gravity(1.0, 0.1, 0.0), 10.0);
+    assert_eq!(player.gravity(-10.0, 1.0, 0.25), 40.0);
+    assert_eq!(profiles.get("nobody"), JumpProfile::default());
+}
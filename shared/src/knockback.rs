@@ -0,0 +1,160 @@
+use bevy::ecs::system::Command;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+///How fast a push without its own decay fades while on the ground, per second
+const GROUND_DECAY: f32 = 8.0;
+///Pushes slower than this just stop
+const MIN_SPEED: f32 = 0.1;
+
+///A push, from a projectile, an explosion or an attack
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct Impulse {
+    ///Added to the entity's velocity
+    pub velocity: Vec3,
+    ///How much of the push fades per second. Without it the push carries on until the entity is
+    ///back on the ground, where it fades like it was slowed by friction.
+    pub decay: Option<f32>,
+    ///Seconds the entity can't act for, see [`crate::movement::MovementState::Knockback`]
+    pub control_lock: Option<f64>,
+}
+
+///Velocity from pushes, on top of the entity's own movement
+#[derive(Component, Default, Clone, Copy, Debug, PartialEq)]
+pub struct Knockback {
+    pub velocity: Vec3,
+    ///Of the latest push
+    pub decay: Option<f32>,
+    ///Control lock the next movement step still has to start
+    pub pending_lock: Option<f64>,
+}
+
+impl Knockback {
+    pub fn add(&mut self, impulse: &Impulse) {
+        self.velocity += impulse.velocity;
+        self.decay = impulse.decay;
+        self.pending_lock = match (self.pending_lock, impulse.control_lock) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+    }
+
+    ///How far the push moves the entity in `dt` seconds, fading it afterwards
+    pub fn advance(&mut self, grounded: bool, dt: f32) -> Vec3 {
+        let moved = self.velocity * dt;
+
+        let decay = match self.decay {
+            Some(decay) => decay,
+            None if grounded => GROUND_DECAY,
+            None => 0.0,
+        };
+        self.velocity *= (-decay * dt).exp();
+        if self.velocity.length() < MIN_SPEED {
+            self.velocity = Vec3::ZERO;
+        }
+        moved
+    }
+}
+
+///Pushes an entity that has a [`Knockback`], a command so it works the same in the client's app
+///and in the server's rooms: `commands.add(ApplyImpulse { .. })`
+#[derive(Clone, Copy, Debug)]
+pub struct ApplyImpulse {
+    pub entity: Entity,
+    pub impulse: Impulse,
+}
+
+impl Command for ApplyImpulse {
+    fn write(self, world: &mut World) {
+        if let Some(mut knockback) = world.get_mut::<Knockback>(self.entity) {
+            knockback.add(&self.impulse);
+        }
+    }
+}
+
+#[test]
+fn decay() {
+    let mut knockback = Knockback::default();
+    knockback.add(&Impulse {
+        velocity: Vec3::X * 10.0,
+        decay: None,
+        control_lock: Some(0.2),
+    });
+    knockback.add(&Impulse {
+        velocity: Vec3::Z * 10.0,
+        decay: None,
+        control_lock: Some(0.1),
+    });
+    assert_eq!(knockback.pending_lock, Some(0.2));
+
+    //keeps going in the air, fades on the ground
+    let moved = knockback.advance(false, 0.5);
+    assert_eq!(moved, Vec3::new(5.0, 0.0, 5.0));
+    let mut travelled = 0.0;
+    for _ in 0..120 {
+        travelled += knockback.advance(true, 1.0 / 60.0).length();
+    }
+    assert_eq!(knockback.velocity, Vec3::ZERO);
+    assert!(
+        travelled < 10.0f32.hypot(10.0) / GROUND_DECAY * 1.1,
+        "{}",
+        travelled
+    );
+}
+
+#[test]
+fn impulse_command() {
+    use crate::collision::CollisionShape;
+    use crate::jump::JumpProfile;
+    use crate::movement::{
+        self, BodyQuery, MovementCharges, MovementInput, MovementState, PhysicsPosition, TIMESTEP,
+    };
+    use crate::{Physics, PhysicsProperties};
+
+    let mut world = World::new();
+    let entity = world
+        .spawn()
+        .insert_bundle((
+            PhysicsPosition::default(),
+            Physics::default(),
+            PhysicsProperties::default(),
+            CollisionShape::default(),
+            JumpProfile::default(),
+            MovementCharges::default(),
+            MovementState::Grounded,
+            Knockback::default(),
+        ))
+        .id();
+    ApplyImpulse {
+        entity,
+        impulse: Impulse {
+            velocity: Vec3::new(10.0, 10.0, 0.0),
+            decay: None,
+            control_lock: Some(0.25),
+        },
+    }
+    .write(&mut world);
+
+    let mut query = world.query::<BodyQuery>();
+    let dt = TIMESTEP.as_secs_f32();
+    let mut history = vec![];
+    for i in 0..240 {
+        let mut item = query.single_mut(&mut world);
+        let input = MovementInput {
+            direction: -Vec2::X,
+            ..Default::default()
+        };
+        let now = (i + 1) as f64 * dt as f64;
+        movement::step(item.body(), &[], &input, now, dt);
+        history.push((item.position.current, *item.state));
+    }
+
+    //thrown up and away, with no say in it until the lock is over
+    assert!(matches!(history[0].1, MovementState::Knockback { .. }));
+    assert!(history[..30].iter().all(|(p, _)| p.x > 0.0 && p.y > 0.0));
+    assert!(history.iter().any(|(_, s)| *s == MovementState::Airborne));
+    //landed, and walked back past where it started
+    let (at, state) = history.last().unwrap();
+    assert_eq!((at.y, *state), (0.0, MovementState::Grounded));
+    assert!(at.x < 0.0);
+}
@@ -1,42 +1,238 @@
+use std::collections::HashMap;
+
 use bevy::prelude::*;
 
-#[derive(Component)]
+pub mod collision;
+pub mod crypto;
+pub mod enemy;
+pub mod fragment;
+pub mod jump;
+pub mod knockback;
+pub mod master;
+pub mod movement;
+pub mod net;
+pub mod quantize;
+pub mod replication;
+pub mod snapshot;
+
+///How an entity moves, loaded from yaml like [`jump::JumpProfile`] so each kind of character can
+///feel different
+#[derive(Component, Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct PhysicsProperties {
     pub movement_speed_ground: f32,
     pub movement_speed_air: f32,
     pub movement_acceleration: f32,
+    ///Slows walking on the ground in proportion to its speed, per second
+    pub friction: f32,
+    ///Slows walking on the ground by a fixed amount, per second
+    pub static_friction: f32,
+    pub dash_speed: f32,
+    ///Seconds a dash lasts
+    pub dash_duration: f64,
+    ///Fraction of `dash_speed` over the dash, timed from 0 at the start to 1 at the end
+    pub dash_falloff: jump::Curve,
     pub dash_cooldown: f64,
+    ///Upwards speed is multiplied by this when jump is let go of on the way up, so a tap jumps
+    ///lower than a hold
+    pub jump_cut: f32,
+    ///Seconds after walking off a ledge that jumping still works
+    pub coyote_time: f64,
+    ///Seconds a jump press is remembered for, so pressing just before landing still jumps
+    pub jump_buffer: f64,
+    ///Extra jumps that can be made before landing again
+    pub air_jumps: u32,
+    ///Seconds between any jump and an air jump
+    pub air_jump_cooldown: f64,
+    ///Seconds for a used air jump to come back without landing, never if unset
+    pub air_jump_regen: Option<f64>,
+    ///Dashes that can be made before landing again, dashing on the ground is only limited by
+    ///`dash_cooldown`. One by default, like the single air dash there always was
+    pub air_dashes: u32,
+    ///Seconds between any dash and an air dash, only matters when longer than `dash_cooldown`
+    pub air_dash_cooldown: f64,
+    pub air_dash_regen: Option<f64>,
+    ///Seconds from the start of a dash the entity can't be hurt for
+    pub dash_invulnerability: f64,
+}
+
+impl Default for PhysicsProperties {
+    fn default() -> Self {
+        Self {
+            movement_speed_ground: 15.0,
+            movement_speed_air: 1.0,
+            movement_acceleration: 15.0 * 10.0,
+            friction: 5.0,
+            static_friction: 15.0,
+            dash_speed: 50.0,
+            dash_duration: 1.0 / 3.0,
+            dash_falloff: jump::Curve::Points(vec![
+                [0.0, 1.0],
+                [0.5, 1.0],
+                [0.5, 0.25],
+                [0.7, 0.18],
+                [0.8, 0.13],
+                [0.9, 0.04],
+                [1.0, 0.0],
+            ]),
+            dash_cooldown: 0.5,
+            jump_cut: 0.5,
+            coyote_time: 0.1,
+            jump_buffer: 0.1,
+            air_jumps: 0,
+            air_jump_cooldown: 0.2,
+            air_jump_regen: None,
+            air_dashes: 1,
+            air_dash_cooldown: 0.5,
+            air_dash_regen: Some(3.0),
+            dash_invulnerability: 0.2,
+        }
+    }
+}
+
+///Named movement profiles, as written in the profiles file
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct MovementProfiles(pub HashMap<String, PhysicsProperties>);
+
+impl MovementProfiles {
+    pub fn from_yaml(yaml: &str) -> Result<Self, serde_yaml::Error> {
+        serde_yaml::from_str(yaml)
+    }
+
+    ///The named profile, or the default one if the file doesn't have it
+    pub fn get(&self, name: &str) -> PhysicsProperties {
+        self.0.get(name).cloned().unwrap_or_else(|| {
+            warn!("No movement profile named {}, using the default", name);
+            PhysicsProperties::default()
+        })
+    }
 }
 
 #[derive(Component)]
 pub struct Physics {
-    pub gravity_func: fn(f32, f32) -> f32,
     pub velocity: Vec3,
     pub walking_velocity: Vec2,
     pub dash_velocity: Vec3,
     pub last_jump: f64,
+    ///Last time the entity wasn't falling, where [`jump::JumpProfile::falling`] starts
+    pub last_not_falling: f64,
+    pub last_grounded: f64,
+    pub last_jump_press: f64,
+    ///Whether jump was held last step, presses are only counted when it wasn't
+    pub jump_held: bool,
+    ///Going up from a jump that can still be cut short
+    pub jump_rising: bool,
     pub last_dash: f64,
+    ///Picked when the dash starts, turning mid-dash doesn't steer it
+    pub dash_direction: Vec3,
+    pub dashing: bool,
+    pub invulnerable_until: f64,
+}
+
+impl Physics {
+    ///Whether the entity is in the invulnerable part of a dash
+    pub fn is_invulnerable(&self, now: f64) -> bool {
+        now < self.invulnerable_until
+    }
 }
 
 impl Default for Physics {
     fn default() -> Self {
         Self {
-            gravity_func: |_, _| 9.8,
             velocity: Vec3::ZERO,
             walking_velocity: Vec2::ZERO,
             last_jump: 0.0,
+            last_not_falling: 0.0,
+            last_grounded: 0.0,
+            last_jump_press: f64::NEG_INFINITY,
+            jump_held: false,
+            jump_rising: false,
             last_dash: 0.0,
             dash_velocity: Vec3::ZERO,
+            dash_direction: Vec3::ZERO,
+            dashing: false,
+            invulnerable_until: 0.0,
         }
     }
 }
 
+///Marks the entity a client plays as, so others can tell players from everything else
+#[derive(Component, Deserialize, Serialize, Clone, Copy, Default, Debug, PartialEq)]
+pub struct Player;
+
+#[derive(Component, Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Health {
+    pub fn new(max: f32) -> Self {
+        Self { current: max, max }
+    }
+}
+
 use serde::Deserialize;
 use serde::Serialize;
 
+use knockback::Impulse;
+use net::{ClientId, Credentials, RejectReason, RoomInfo};
+use replication::{NetId, ReplicationMessage};
+
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub enum NetworkingAction {
     Print(String),
     Location(Quat, Vec3),
     Heartbeat,
+    ///Public keys of an encrypted session, sent in the clear by the client and answered by the
+    ///server. Every frame after it is sealed. The server's answer also has the public key of its
+    ///[`crypto::Identity`], for clients that weren't given it.
+    KeyExchange {
+        key: [u8; 32],
+        identity: Option<[u8; 32]>,
+    },
+    ///First thing a client sends, nothing else is accepted until the server answers it
+    Hello(Credentials),
+    ///Sent by the server once it accepts the hello. `session` is a token to log in with next
+    ///time, if the server has authentication turned on.
+    Welcome {
+        client_id: ClientId,
+        udp_key: u64,
+        session: Option<String>,
+    },
+    ///Sent by the server right before it drops a connection it didn't accept
+    Rejected(RejectReason),
+    ///Sent to everyone right before the server stops, with the reason
+    ServerShutdown(String),
+    Replication(ReplicationMessage),
+    ///Sent by the client over udp until the server starts sending snapshots there, so the
+    ///server can tie the udp endpoint to the tcp connection. `key` comes from the welcome so
+    ///nobody else can claim the connection.
+    UdpHello {
+        client_id: ClientId,
+        key: u64,
+    },
+
+    CreateRoom(String),
+    ListRooms,
+    JoinRoom(String),
+    ///Join a room without a player, receiving everything in it
+    SpectateRoom(String),
+    LeaveRoom,
+    RoomList(Vec<RoomInfo>),
+    ///`player` is the replicated entity the client controls itself, so it can skip applying
+    ///server state to it. Spectators don't get one. `epoch` is different for every join, only
+    ///snapshots carrying it belong to this room.
+    RoomJoined {
+        room: String,
+        player: Option<NetId>,
+        epoch: u64,
+    },
+    RoomLeft,
+    RoomError(String),
+    ///A push the server gave the client's player. Clients move their own player, so it's
+    ///applied there.
+    Impulse(Impulse),
+    ///Client to server, one of its projectiles hit a replicated enemy or player. The server
+    ///checks it and pushes the target.
+    Hit { target: NetId, impulse: Impulse },
 }
@@ -0,0 +1,101 @@
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::fragment::Reassembler;
+
+pub const DEFAULT_MASTER_PORT: u16 = 7790;
+
+///Game servers send a heartbeat this often
+pub const HEARTBEAT_SECONDS: u64 = 10;
+///A server is unlisted once it missed this many heartbeats
+pub const MISSED_HEARTBEATS: u64 = 3;
+///Longer server names and maps are cut short by the master
+pub const MAX_NAME_CHARS: usize = 48;
+pub const MAX_MAP_CHARS: usize = 32;
+
+///What a game server tells the master about itself
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ServerInfo {
+    pub name: String,
+    pub map: String,
+    pub players: usize,
+    ///The game port, the master takes the ip from where the heartbeat came from
+    pub port: u16,
+}
+
+impl ServerInfo {
+    ///Cuts the name and map down to what the master keeps
+    pub fn truncated(mut self) -> Self {
+        self.name = self.name.chars().take(MAX_NAME_CHARS).collect();
+        self.map = self.map.chars().take(MAX_MAP_CHARS).collect();
+        self
+    }
+}
+
+///A server in the master's list
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ServerListing {
+    pub addr: SocketAddr,
+    pub info: ServerInfo,
+}
+
+///Everything sent to and from the master, one json message per datagram. Lists that don't fit
+///are fragmented, see [`crate::fragment`].
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub enum MasterMessage {
+    ///Lists the server, or keeps it listed
+    Heartbeat(ServerInfo),
+    ///Asks for the list. Without the cookie from a [`MasterMessage::Challenge`] the master only
+    ///answers with one, so nobody can have the list sent to an address they don't own.
+    Query {
+        cookie: Option<u32>,
+    },
+    ///Sent instead of the list, the query has to be repeated with this cookie
+    Challenge(u32),
+    List(Vec<ServerListing>),
+}
+
+pub fn encode(message: &MasterMessage) -> Vec<u8> {
+    serde_json::to_vec(message).expect("MasterMessage is always serializable")
+}
+
+pub fn decode(data: &[u8]) -> Result<MasterMessage, serde_json::Error> {
+    serde_json::from_slice(data)
+}
+
+///Asks the master for its list, giving up after `timeout`
+pub fn query<A: ToSocketAddrs>(master: A, timeout: Duration) -> io::Result<Vec<ServerListing>> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect(master)?;
+    socket.send(&encode(&MasterMessage::Query { cookie: None }))?;
+
+    let deadline = Instant::now() + timeout;
+    let mut reassembler = Reassembler::default();
+    let mut buf = [0; 2048];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        socket.set_read_timeout(Some(remaining))?;
+
+        let len = socket.recv(&mut buf)?;
+        let frame = match reassembler.receive(&buf[..len], Instant::now()) {
+            Some(f) => f,
+            None => continue,
+        };
+        match decode(&frame) {
+            Ok(MasterMessage::List(servers)) => return Ok(servers),
+            Ok(MasterMessage::Challenge(cookie)) => {
+                let query = MasterMessage::Query {
+                    cookie: Some(cookie),
+                };
+                socket.send(&encode(&query))?;
+            }
+            _ => {}
+        }
+    }
+}
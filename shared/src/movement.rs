@@ -0,0 +1,918 @@
+use std::time::Duration;
+
+use bevy::ecs::query::WorldQuery;
+use bevy::ecs::system::Command;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::collision::{self, CollisionShape, Grounded, Solid, GROUND_PROBE, STEP_HEIGHT};
+use crate::jump::JumpProfile;
+use crate::knockback::Knockback;
+use crate::{Physics, PhysicsProperties};
+
+///Length of one movement step. Movement only ever advances by whole steps, so it plays out the
+///same at any frame rate.
+pub const TIMESTEP: Duration = Duration::from_nanos(1_000_000_000 / 120);
+///A frame never runs more steps than this, a long stall is dropped instead of caught up
+const MAX_STEPS_PER_FRAME: u32 = 8;
+
+///Turns frame times into whole movement steps
+#[derive(Default)]
+pub struct FixedTimestep {
+    accumulator: Duration,
+    steps: u64,
+    ///Set while the steps of the current frame are being run
+    looping: bool,
+    steps_this_frame: u32,
+}
+
+impl FixedTimestep {
+    ///Called until it returns false, returns true once for every step the frame's time adds up
+    ///to. `delta` is only counted on the first call of a frame.
+    pub fn next_step(&mut self, delta: Duration) -> bool {
+        if !self.looping {
+            self.accumulator += delta;
+            self.looping = true;
+            self.steps_this_frame = 0;
+        }
+
+        if self.accumulator >= TIMESTEP && self.steps_this_frame < MAX_STEPS_PER_FRAME {
+            self.accumulator -= TIMESTEP;
+            self.steps += 1;
+            self.steps_this_frame += 1;
+            return true;
+        }
+
+        self.accumulator = self.accumulator.min(TIMESTEP);
+        self.looping = false;
+        false
+    }
+
+    ///How far into the next step the frame is, 0 to 1
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator.as_secs_f64() / TIMESTEP.as_secs_f64()).min(1.0) as f32
+    }
+
+    ///Seconds of movement simulated so far
+    pub fn elapsed(&self) -> f64 {
+        self.steps as f64 * TIMESTEP.as_secs_f64()
+    }
+}
+
+///Where movement put the entity at the last two steps. The `Transform` is drawn in between, so
+///it moves smoothly even when frames and steps don't line up.
+#[derive(Component, Default, Clone, Copy, Debug, PartialEq)]
+pub struct PhysicsPosition {
+    pub previous: Vec3,
+    pub current: Vec3,
+}
+
+impl PhysicsPosition {
+    pub fn new(at: Vec3) -> Self {
+        Self {
+            previous: at,
+            current: at,
+        }
+    }
+
+    pub fn interpolate(&self, alpha: f32) -> Vec3 {
+        self.previous.lerp(self.current, alpha)
+    }
+}
+
+///What the player asks for during a step
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct MovementInput {
+    ///World space, unit length or zero
+    pub direction: Vec2,
+    ///World space direction the camera looks along, dashes go this way if no direction is held
+    pub facing: Vec2,
+    pub jump: bool,
+    pub dash: bool,
+}
+
+///Sent when an entity starts dashing, for effects and combat
+#[derive(Clone, Copy, Debug)]
+pub struct DashStarted {
+    pub entity: Entity,
+    pub direction: Vec3,
+    ///Movement time the dash's invulnerability ends at
+    pub invulnerable_until: f64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DashEnded {
+    pub entity: Entity,
+}
+
+///What an entity's movement is doing, decides which of its inputs are listened to
+#[derive(Component, Deserialize, Serialize, Clone, Copy, Default, Debug, PartialEq)]
+pub enum MovementState {
+    Grounded,
+    #[default]
+    Airborne,
+    Dashing,
+    ///No control until the movement time `until`
+    Stunned {
+        until: f64,
+    },
+    ///Thrown by a hit, no control until the movement time `until`
+    Knockback {
+        until: f64,
+    },
+    ///Only left by setting the state directly, like on respawn
+    Dead,
+}
+
+///Things an entity can be asked to do, allowed or not depending on its [`MovementState`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Walk,
+    Jump,
+    Dash,
+    Attack,
+}
+
+impl MovementState {
+    pub fn allows(&self, action: Action) -> bool {
+        match self {
+            MovementState::Grounded | MovementState::Airborne => true,
+            MovementState::Dashing => action != Action::Dash,
+            MovementState::Stunned { .. }
+            | MovementState::Knockback { .. }
+            | MovementState::Dead => false,
+        }
+    }
+
+    ///States only give way to ones at least as important
+    fn priority(&self) -> u8 {
+        match self {
+            MovementState::Grounded | MovementState::Airborne => 0,
+            MovementState::Dashing => 1,
+            MovementState::Stunned { .. } | MovementState::Knockback { .. } => 2,
+            MovementState::Dead => 3,
+        }
+    }
+
+    ///Switches to a state from outside of the step. Returns the transition if it took, a stun
+    ///doesn't override death.
+    fn interrupt(&mut self, to: MovementState) -> Option<(MovementState, MovementState)> {
+        if to.priority() < self.priority() || to == *self {
+            return None;
+        }
+        let from = std::mem::replace(self, to);
+        Some((from, to))
+    }
+
+    ///Where movement itself takes the state after a step
+    fn next(&self, dashing: bool, grounded: bool, now: f64) -> MovementState {
+        match *self {
+            MovementState::Dead => MovementState::Dead,
+            MovementState::Stunned { until } | MovementState::Knockback { until }
+                if now < until =>
+            {
+                *self
+            }
+            _ if dashing => MovementState::Dashing,
+            _ if grounded => MovementState::Grounded,
+            _ => MovementState::Airborne,
+        }
+    }
+}
+
+///Sent whenever an entity's [`MovementState`] changes
+#[derive(Clone, Copy, Debug)]
+pub struct MovementStateChanged {
+    pub entity: Entity,
+    pub from: MovementState,
+    pub to: MovementState,
+}
+
+///Switches an entity's [`MovementState`] from outside of movement, like a hit stunning it, and
+///sends the [`MovementStateChanged`] if it took: `commands.add(Interrupt { .. })`
+#[derive(Clone, Copy, Debug)]
+pub struct Interrupt {
+    pub entity: Entity,
+    ///Times in it are movement times, like [`FixedTimestep::elapsed`]
+    pub to: MovementState,
+}
+
+impl Command for Interrupt {
+    fn write(self, world: &mut World) {
+        let changed = match world.get_mut::<MovementState>(self.entity) {
+            Some(mut state) => state.interrupt(self.to),
+            None => return,
+        };
+        if let (Some((from, to)), Some(mut events)) = (
+            changed,
+            world.get_resource_mut::<Events<MovementStateChanged>>(),
+        ) {
+            events.send(MovementStateChanged {
+                entity: self.entity,
+                from,
+                to,
+            });
+        }
+    }
+}
+
+///What came of a movement step
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct StepOutcome {
+    ///What the body stands on after the step
+    pub ground: Option<Grounded>,
+    ///Direction of a dash that started this step
+    pub dash_started: Option<Vec3>,
+    pub dash_ended: bool,
+    ///The state before and after, if the step changed it
+    pub state_changed: Option<(MovementState, MovementState)>,
+}
+
+///Uses of something that can only be done a few times in the air. All come back on landing, and
+///one at a time while below `max` if the ability regenerates.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct Charges {
+    pub current: u32,
+    pub max: u32,
+    ///When the charge being regenerated started coming back
+    pub regen_since: f64,
+}
+
+impl Charges {
+    fn update(&mut self, max: u32, regen: Option<f64>, grounded: bool, now: f64) {
+        self.max = max;
+        if grounded || self.current >= max {
+            self.current = max;
+            self.regen_since = now;
+            return;
+        }
+
+        if let Some(regen) = regen {
+            while self.current < max && now - self.regen_since >= regen {
+                self.current += 1;
+                self.regen_since += regen;
+            }
+        }
+    }
+
+    ///Uses a charge if there is one
+    fn take(&mut self) -> bool {
+        if self.current == 0 {
+            return false;
+        }
+        self.current -= 1;
+        true
+    }
+
+    ///0 to 1, how far along the next charge is
+    pub fn regen_progress(&self, regen: Option<f64>, now: f64) -> f32 {
+        match regen {
+            Some(regen) if self.current < self.max => {
+                ((now - self.regen_since) / regen).clamp(0.0, 1.0) as f32
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+///Air jumps and air dashes left, also shown on the HUD
+#[derive(Component, Clone, Copy, Default, Debug, PartialEq)]
+pub struct MovementCharges {
+    pub air_jumps: Charges,
+    pub air_dashes: Charges,
+}
+
+///The components of an entity that movement reads and changes
+pub struct Body<'a> {
+    pub position: &'a mut PhysicsPosition,
+    pub phys: &'a mut Physics,
+    pub props: &'a PhysicsProperties,
+    pub shape: &'a CollisionShape,
+    pub jump: &'a JumpProfile,
+    pub charges: &'a mut MovementCharges,
+    pub state: &'a mut MovementState,
+    pub knockback: &'a mut Knockback,
+}
+
+///Queries the components of a [`Body`]
+#[derive(WorldQuery)]
+#[world_query(mutable)]
+pub struct BodyQuery {
+    pub position: &'static mut PhysicsPosition,
+    pub phys: &'static mut Physics,
+    pub props: &'static PhysicsProperties,
+    pub shape: &'static CollisionShape,
+    pub jump: &'static JumpProfile,
+    pub charges: &'static mut MovementCharges,
+    pub state: &'static mut MovementState,
+    pub knockback: &'static mut Knockback,
+}
+
+impl<'w> BodyQueryItem<'w> {
+    pub fn body(&mut self) -> Body<'_> {
+        Body {
+            position: &mut self.position,
+            phys: &mut self.phys,
+            props: self.props,
+            shape: self.shape,
+            jump: self.jump,
+            charges: &mut self.charges,
+            state: &mut self.state,
+            knockback: &mut self.knockback,
+        }
+    }
+}
+
+///Advances movement by one step of `dt` seconds, `now` being the movement time after the step.
+///The body slides along `solids` and can stand on top of them.
+pub fn step(body: Body, solids: &[Solid], input: &MovementInput, now: f64, dt: f32) -> StepOutcome {
+    let mut outcome = StepOutcome::default();
+    let Body {
+        position,
+        phys,
+        props,
+        shape,
+        jump,
+        charges,
+        state,
+        knockback,
+    } = body;
+    position.previous = position.current;
+    let translation = &mut position.current;
+    let before = *state;
+
+    //a push with a control lock knocks the entity out of whatever it was doing, and an upwards
+    //push becomes part of the entity's own velocity so gravity takes it back down
+    if let Some(lock) = knockback.pending_lock.take() {
+        state.interrupt(MovementState::Knockback { until: now + lock });
+    }
+    phys.velocity.y += std::mem::take(&mut knockback.velocity.y);
+
+    //the state decides which inputs count. Jump is only ignored when pressed, so holding it
+    //through a stun doesn't jump once the stun is over.
+    let input = &MovementInput {
+        direction: if state.allows(Action::Walk) {
+            input.direction
+        } else {
+            Vec2::ZERO
+        },
+        facing: input.facing,
+        jump: input.jump,
+        dash: input.dash && state.allows(Action::Dash),
+    };
+    //an interrupted dash stops right away
+    if phys.dashing && !state.allows(Action::Walk) {
+        phys.dashing = false;
+        phys.dash_direction = Vec3::ZERO;
+        outcome.dash_ended = true;
+    }
+
+    let is_in_air = collision::ground(*translation, shape, solids, GROUND_PROBE).is_none();
+
+    let mut movement2d = input.direction;
+    movement2d *= if is_in_air {
+        props.movement_speed_air
+    } else {
+        props.movement_speed_ground
+    };
+    movement2d *= props.movement_acceleration / props.movement_speed_ground;
+
+    if !is_in_air || phys.velocity.y > 0.0 {
+        phys.last_not_falling = now;
+    }
+    let gravity = jump.gravity(
+        phys.velocity.y,
+        (now - phys.last_jump) as f32,
+        (now - phys.last_not_falling) as f32,
+    );
+    phys.velocity -= Vec3::new(0.0, gravity * dt, 0.0);
+    if let Some(max_fall_speed) = jump.max_fall_speed {
+        phys.velocity.y = phys.velocity.y.max(-max_fall_speed);
+    }
+
+    //jumping section
+    if !is_in_air {
+        phys.last_grounded = now;
+    }
+    if input.jump && !phys.jump_held && state.allows(Action::Jump) {
+        phys.last_jump_press = now;
+    }
+    phys.jump_held = input.jump;
+
+    charges
+        .air_jumps
+        .update(props.air_jumps, props.air_jump_regen, !is_in_air, now);
+    charges
+        .air_dashes
+        .update(props.air_dashes, props.air_dash_regen, !is_in_air, now);
+
+    let buffered =
+        phys.last_jump_press > phys.last_jump && now - phys.last_jump_press <= props.jump_buffer;
+    let coyote =
+        phys.last_jump < phys.last_grounded && now - phys.last_grounded <= props.coyote_time;
+    //a jump off the ground while still allowed to, otherwise one of the air jumps. A press from
+    //before a stun is still buffered, but doesn't go off during it.
+    let jumped = state.allows(Action::Jump)
+        && buffered
+        && (coyote
+            || (now - phys.last_jump >= props.air_jump_cooldown && charges.air_jumps.take()));
+    if jumped {
+        phys.velocity.y = jump.jump_velocity;
+        phys.last_jump = now;
+        phys.jump_rising = true;
+    } else if phys.jump_rising && (!input.jump || phys.velocity.y <= 0.0) {
+        if phys.velocity.y > 0.0 {
+            phys.velocity.y *= props.jump_cut;
+        }
+        phys.jump_rising = false;
+    }
+
+    //walking section
+    if !is_in_air {
+        //slow the player when on ground
+        let mut friction_vel = phys.walking_velocity * -props.friction * dt;
+
+        if phys.walking_velocity.length() < 0.1 {
+            //For low velocities, just stop the player
+            friction_vel = phys.walking_velocity * -1.0;
+        } else {
+            friction_vel += phys.walking_velocity.normalize() * -props.static_friction * dt;
+        };
+        phys.walking_velocity += friction_vel;
+    }
+    phys.walking_velocity += movement2d * dt;
+
+    if phys.walking_velocity.length() > props.movement_speed_ground {
+        phys.walking_velocity = phys.walking_velocity.normalize() * props.movement_speed_ground;
+    }
+
+    //dashing section, the direction is kept for the whole dash
+    let direction = if input.direction != Vec2::ZERO {
+        input.direction
+    } else {
+        input.facing
+    };
+    if input.dash
+        && direction != Vec2::ZERO
+        && phys.last_dash < now - props.dash_cooldown
+        && (!is_in_air
+            || (now - phys.last_dash >= props.air_dash_cooldown && charges.air_dashes.take()))
+    {
+        phys.last_dash = now;
+        phys.dash_direction = Vec3::new(direction.x, 0.0, direction.y);
+        phys.invulnerable_until = now + props.dash_invulnerability;
+        phys.dashing = true;
+        outcome.dash_started = Some(phys.dash_direction);
+    }
+
+    let dash_percent = (now - phys.last_dash) / props.dash_duration;
+    phys.dash_velocity =
+        phys.dash_direction * props.dash_falloff.sample(dash_percent as f32) * props.dash_speed;
+    if phys.dashing && dash_percent > 1.0 {
+        phys.dashing = false;
+        outcome.dash_ended = true;
+    }
+
+    let walking = Vec3::new(phys.walking_velocity.x, 0.0, phys.walking_velocity.y);
+    let motion =
+        (phys.velocity + walking + phys.dash_velocity) * dt + knockback.advance(!is_in_air, dt);
+    let blocked = collision::move_and_slide(translation, shape, motion, solids);
+
+    //landing, or hitting a ceiling
+    if blocked.y {
+        phys.velocity.y = 0.0;
+    }
+    //pushed into a wall
+    if blocked.x {
+        knockback.velocity.x = 0.0;
+    }
+    if blocked.z {
+        knockback.velocity.z = 0.0;
+    }
+
+    //keep to the ground walking down ramps, instead of flying off them
+    if !is_in_air && phys.velocity.y <= 0.0 {
+        if let Some(ground) = collision::ground(*translation, shape, solids, STEP_HEIGHT) {
+            translation.y = ground.height;
+        }
+    }
+
+    outcome.ground = collision::ground(*translation, shape, solids, GROUND_PROBE);
+
+    *state = state.next(phys.dashing, outcome.ground.is_some(), now);
+    if *state != before {
+        outcome.state_changed = Some((before, *state));
+    }
+    outcome
+}
+
+///The timings the tests count steps with, so retuning the defaults doesn't break them
+#[cfg(test)]
+fn test_props() -> PhysicsProperties {
+    PhysicsProperties {
+        dash_duration: 1.0 / 3.0,
+        dash_cooldown: 0.5,
+        coyote_time: 0.1,
+        jump_buffer: 0.1,
+        dash_invulnerability: 0.2,
+        ..Default::default()
+    }
+}
+
+///An entity's movement components outside of a world, stepped one [`TIMESTEP`] at a time
+#[cfg(test)]
+struct TestBody {
+    position: PhysicsPosition,
+    phys: Physics,
+    props: PhysicsProperties,
+    charges: MovementCharges,
+    state: MovementState,
+    knockback: Knockback,
+    steps: u64,
+}
+
+#[cfg(test)]
+impl TestBody {
+    fn new(props: PhysicsProperties, at: Vec3) -> Self {
+        Self {
+            position: PhysicsPosition::new(at),
+            phys: Physics {
+                last_jump: -100.0,
+                last_dash: -100.0,
+                ..Default::default()
+            },
+            props,
+            charges: MovementCharges::default(),
+            state: MovementState::default(),
+            knockback: Knockback::default(),
+            steps: 0,
+        }
+    }
+
+    ///Movement time at the end of the last step
+    fn now(&self) -> f64 {
+        self.steps as f64 * TIMESTEP.as_secs_f64()
+    }
+
+    fn step(&mut self, solids: &[Solid], input: &MovementInput) -> StepOutcome {
+        self.steps += 1;
+        let now = self.now();
+        let body = Body {
+            position: &mut self.position,
+            phys: &mut self.phys,
+            props: &self.props,
+            shape: &CollisionShape::default(),
+            jump: &JumpProfile::default(),
+            charges: &mut self.charges,
+            state: &mut self.state,
+            knockback: &mut self.knockback,
+        };
+        step(body, solids, input, now, TIMESTEP.as_secs_f32())
+    }
+}
+
+#[test]
+fn frame_rate_independent() {
+    let trajectory = |fps: u64| {
+        let mut clock = FixedTimestep::default();
+        let mut body = TestBody::new(test_props(), Vec3::ZERO);
+
+        let mut trajectory = vec![];
+        //two seconds of frames, frame times are whole nanoseconds like a real clock's
+        let nanos = |frame: u64| frame * 1_000_000_000 / fps;
+        for frame in 0..2 * fps {
+            let started = nanos(frame) as f64 / 1e9;
+            let input = MovementInput {
+                direction: Vec2::X,
+                jump: started < 0.1,
+                dash: (0.5..0.6).contains(&started),
+                ..Default::default()
+            };
+
+            let delta = Duration::from_nanos(nanos(frame + 1) - nanos(frame));
+            while clock.next_step(delta) {
+                body.step(&[], &input);
+                assert_eq!(body.now(), clock.elapsed());
+                trajectory.push(body.position.current);
+            }
+            assert!((0.0..=1.0).contains(&clock.alpha()));
+        }
+        trajectory
+    };
+
+    let at_60 = trajectory(60);
+    assert_eq!(at_60.len(), 240);
+    assert!(at_60.iter().any(|p| p.y > 1.0), "never jumped");
+    assert_eq!(trajectory(30), at_60);
+    assert_eq!(trajectory(240), at_60);
+}
+
+#[test]
+fn jump_on_platform() {
+    let platform = collision::Collider::Cuboid {
+        half_extents: Vec3::new(5.0, 1.0, 5.0),
+    }
+    .solid(None, Vec3::new(0.0, 1.0, 0.0));
+    let mut body = TestBody::new(test_props(), Vec3::new(0.0, 2.0, 0.0));
+
+    let heights: Vec<_> = (0..240)
+        .map(|i| {
+            let input = MovementInput {
+                jump: (10..60).contains(&i),
+                ..Default::default()
+            };
+            let outcome = body.step(&[platform], &input);
+            (body.position.current.y, outcome.ground.is_some())
+        })
+        .collect();
+
+    //stood on the platform, jumped off its top, and landed back on it
+    assert!(heights[..10].iter().all(|&h| h == (2.0, true)));
+    assert!(heights[11..20]
+        .iter()
+        .all(|&(y, grounded)| y > 2.0 && !grounded));
+    assert_eq!(heights.last(), Some(&(2.0, true)));
+}
+
+#[test]
+fn jump_assists() {
+    //the 0.1s windows are 12 steps
+    let window = 12;
+
+    let run = |start: Vec3, solids: &[Solid], input: &dyn Fn(usize) -> MovementInput| {
+        let mut body = TestBody::new(test_props(), start);
+        (0..240)
+            .map(|i| {
+                let outcome = body.step(solids, &input(i));
+                (body.position.current, outcome.ground.is_some())
+            })
+            .collect::<Vec<_>>()
+    };
+    let apex =
+        |trajectory: &[(Vec3, bool)]| trajectory.iter().map(|(p, _)| p.y).fold(f32::MIN, f32::max);
+    let jump_at = |from: usize, to: usize| {
+        move |i| MovementInput {
+            jump: (from..to).contains(&i),
+            ..Default::default()
+        }
+    };
+
+    //a tap jumps lower than a hold, and holding doesn't jump again after landing
+    let tap = run(Vec3::ZERO, &[], &jump_at(0, 1));
+    let hold = run(Vec3::ZERO, &[], &jump_at(0, 240));
+    assert!(
+        apex(&tap) < apex(&hold) / 2.0,
+        "{} {}",
+        apex(&tap),
+        apex(&hold)
+    );
+    assert!(hold[200..]
+        .iter()
+        .all(|&(p, grounded)| p.y == 0.0 && grounded));
+
+    //walking off a platform
+    let platform = collision::Collider::Cuboid {
+        half_extents: Vec3::new(5.0, 1.0, 5.0),
+    }
+    .solid(None, Vec3::new(0.0, 1.0, 0.0));
+    let walk_off = |from: usize| {
+        move |i| MovementInput {
+            direction: Vec2::X,
+            jump: (from..from + 30).contains(&i),
+            ..Default::default()
+        }
+    };
+    let start = Vec3::new(5.5, 2.0, 0.0);
+    let left = run(start, &[platform], &walk_off(1000))
+        .iter()
+        .position(|&(_, grounded)| !grounded)
+        .unwrap();
+    let late = run(start, &[platform], &walk_off(left + window / 2));
+    assert!(apex(&late) > 3.0, "no coyote jump {}", apex(&late));
+    let too_late = run(start, &[platform], &walk_off(left + window * 2));
+    assert!(apex(&too_late) <= 2.0);
+
+    //falling onto the floor
+    let start = Vec3::new(0.0, 3.0, 0.0);
+    let landed = run(start, &[], &jump_at(1000, 1000))
+        .iter()
+        .position(|&(_, grounded)| grounded)
+        .unwrap();
+    let early = run(start, &[], &jump_at(landed - window / 2, 240));
+    assert!(apex(&early[landed..]) > 1.0, "jump wasn't buffered");
+    let too_early = run(start, &[], &jump_at(landed - window * 2, 240));
+    assert_eq!(apex(&too_early[landed..]), 0.0);
+}
+
+#[test]
+fn air_charges() {
+    let mut body = TestBody::new(
+        PhysicsProperties {
+            air_jumps: 1,
+            air_jump_cooldown: 0.2,
+            air_dashes: 1,
+            air_dash_cooldown: 0.55,
+            air_dash_regen: Some(0.25),
+            ..test_props()
+        },
+        Vec3::ZERO,
+    );
+
+    let mut history = vec![];
+    for i in 0..480 {
+        //jump, try two air jumps, the first too soon after the jump
+        let jump = [0..5, 12..15, 40..45, 80..85]
+            .iter()
+            .any(|presses| presses.contains(&i));
+        let input = MovementInput {
+            direction: Vec2::X,
+            jump,
+            //the second dash is past dash_cooldown but not air_dash_cooldown
+            dash: [50, 113, 118].contains(&i),
+            ..Default::default()
+        };
+        body.step(&[], &input);
+        history.push((body.phys.velocity.y, body.charges));
+    }
+
+    let air_jumps = |i: usize| history[i].1.air_jumps.current;
+    let air_dashes = |i: usize| history[i].1.air_dashes.current;
+    assert_eq!(air_jumps(0), 1);
+    assert_eq!(air_jumps(12), 1, "air jumped during the cooldown");
+    assert_eq!(air_jumps(40), 0);
+    assert_eq!(history[40].0, 15.0);
+    assert_eq!(history[80].1.air_jumps.current, 0);
+    assert!(history[80].0 < 15.0, "jumped without a charge");
+
+    assert_eq!(air_dashes(49), 1);
+    assert_eq!(air_dashes(50), 0);
+    //comes back a quarter second later, 30 steps
+    assert_eq!(air_dashes(79), 0);
+    assert_eq!(air_dashes(81), 1);
+    assert_eq!(air_dashes(113), 1, "air dashed during the cooldown");
+    assert_eq!(air_dashes(118), 0);
+
+    //everything is back after landing
+    let landed = &history.last().unwrap().1;
+    assert_eq!(landed.air_jumps.current, 1);
+    assert_eq!(landed.air_dashes.max, 1);
+}
+
+#[test]
+fn dash_direction_locked() {
+    let mut body = TestBody::new(test_props(), Vec3::ZERO);
+
+    let mut outcomes = vec![];
+    for i in 0..240 {
+        let input = MovementInput {
+            //dash with nothing held, then try to steer it
+            direction: if i < 10 { Vec2::ZERO } else { Vec2::Y },
+            facing: Vec2::X,
+            dash: i == 0 || i == 50,
+            ..Default::default()
+        };
+        let outcome = body.step(&[], &input);
+        let invulnerable = body.phys.is_invulnerable(body.now());
+        outcomes.push((outcome, body.phys.dash_velocity, invulnerable));
+    }
+
+    assert_eq!(outcomes[0].0.dash_started, Some(Vec3::X));
+    assert!(outcomes[0].2);
+    assert!(!outcomes[30].2, "invulnerable for too long");
+    //the input turned, the dash didn't
+    assert_eq!(outcomes[20].1.normalize(), Vec3::X);
+
+    //ends a third of a second in, and the second press is during the cooldown
+    let ended: Vec<_> = (0..240).filter(|&i| outcomes[i].0.dash_ended).collect();
+    assert_eq!(ended.len(), 1);
+    assert!((39..=41).contains(&ended[0]), "{:?}", ended);
+    assert!(outcomes.iter().skip(1).all(|o| o.0.dash_started.is_none()));
+}
+
+#[test]
+fn state_transitions() {
+    let mut body = TestBody::new(test_props(), Vec3::ZERO);
+
+    let mut transitions = vec![];
+    let mut dash_ended = vec![];
+    for i in 0..240 {
+        let now = (i + 1) as f64 * TIMESTEP.as_secs_f64();
+        //a hit lands mid-dash, and the player is killed once the stun is over
+        if i == 20 {
+            let stun = MovementState::Stunned { until: now + 0.5 };
+            transitions.extend(body.state.interrupt(stun).map(|t| (i, t)));
+        }
+        if i == 200 {
+            transitions.extend(body.state.interrupt(MovementState::Dead).map(|t| (i, t)));
+            assert_eq!(
+                body.state
+                    .interrupt(MovementState::Stunned { until: 1000.0 }),
+                None
+            );
+        }
+
+        let input = MovementInput {
+            direction: Vec2::X,
+            dash: i == 10,
+            jump: i >= 60,
+            ..Default::default()
+        };
+        let outcome = body.step(&[], &input);
+        transitions.extend(outcome.state_changed.map(|t| (i, t)));
+        if outcome.dash_ended {
+            dash_ended.push(i);
+        }
+        if matches!(
+            body.state,
+            MovementState::Stunned { .. } | MovementState::Dead
+        ) {
+            assert_eq!(
+                body.phys.dash_velocity,
+                Vec3::ZERO,
+                "moved while stunned at {}",
+                i
+            );
+        }
+    }
+
+    //jump was held through the stun, that isn't a press once it's over
+    let states: Vec<_> = transitions.iter().map(|(_, (_, to))| *to).collect();
+    assert!(
+        matches!(
+            states[..],
+            [
+                MovementState::Grounded,
+                MovementState::Dashing,
+                MovementState::Stunned { .. },
+                MovementState::Grounded,
+                MovementState::Dead
+            ]
+        ),
+        "{:?}",
+        transitions
+    );
+    assert_eq!(dash_ended, vec![20]);
+
+    //jump pressed while falling, one step before a stun. It's still buffered when the body
+    //lands, but the stun keeps it from jumping.
+    let start = Vec3::new(0.0, 1.0, 0.0);
+    let mut fall = TestBody::new(test_props(), start);
+    let landed = (0..240)
+        .position(|_| fall.step(&[], &MovementInput::default()).ground.is_some())
+        .unwrap();
+    let mut body = TestBody::new(test_props(), start);
+    for i in 0..landed + 120 {
+        if i == landed - 2 {
+            let stun = MovementState::Stunned {
+                until: body.now() + 0.5,
+            };
+            assert!(body.state.interrupt(stun).is_some());
+        }
+        let input = MovementInput {
+            jump: i == landed - 3,
+            ..Default::default()
+        };
+        body.step(&[], &input);
+        if i >= landed {
+            assert_eq!(
+                body.position.current.y, 0.0,
+                "jumped while stunned at {}",
+                i
+            );
+        }
+    }
+}
+
+#[test]
+fn interrupt_command() {
+    let mut world = World::new();
+    world.init_resource::<Events<MovementStateChanged>>();
+    let entity = world.spawn().insert(MovementState::Grounded).id();
+
+    Interrupt {
+        entity,
+        to: MovementState::Dead,
+    }
+    .write(&mut world);
+    //death isn't interrupted by a stun, so there is only one change
+    Interrupt {
+        entity,
+        to: MovementState::Stunned { until: 1.0 },
+    }
+    .write(&mut world);
+
+    assert_eq!(
+        world.get::<MovementState>(entity),
+        Some(&MovementState::Dead)
+    );
+    let events = world.resource::<Events<MovementStateChanged>>();
+    let changes: Vec<_> = events
+        .get_reader()
+        .iter(events)
+        .map(|e| (e.entity, e.from, e.to))
+        .collect();
+    assert_eq!(
+        changes,
+        vec![(entity, MovementState::Grounded, MovementState::Dead)]
+    );
+}
@@ -0,0 +1,142 @@
+use std::io::{Read, Write};
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+
+use crate::NetworkingAction;
+
+pub const DEFAULT_PORT: u16 = 7777;
+pub const DEFAULT_WEBSOCKET_PORT: u16 = 7778;
+
+///First byte of a compressed frame, the rest is deflated json. Plain frames are json and never
+///start with it.
+pub const COMPRESSED: u8 = 3;
+///Messages smaller than this aren't worth compressing
+pub const COMPRESSION_THRESHOLD: usize = 512;
+///A compressed frame may not inflate to more than this, so a small frame can't eat all memory
+const MAX_DECOMPRESSED: u64 = 16 * 1024 * 1024;
+
+///Server side id for a single connection
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ClientId(pub u64);
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct RoomInfo {
+    pub name: String,
+    pub players: usize,
+    pub spectators: usize,
+}
+
+///What a client proves who it is with
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum Credentials {
+    ///Only accepted by servers without authentication
+    Anonymous,
+    ///A name and the secret the server has on file for it
+    Secret { name: String, secret: String },
+    ///A session token the server issued earlier
+    Token(String),
+}
+
+///Why the server refused a connection
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub enum RejectReason {
+    ///The server needs a name and secret or a session token
+    CredentialsRequired,
+    UnknownName,
+    WrongSecret,
+    ///The token is malformed or wasn't signed by this server
+    InvalidToken,
+    TokenExpired,
+    ///Someone with this name is already connected
+    NameInUse,
+    ///The server only accepts encrypted sessions
+    EncryptionRequired,
+    ///Banned by an admin, until `expires` (unix seconds) or for good
+    Banned {
+        reason: String,
+        expires: Option<u64>,
+    },
+    ///The server only lets in players on its allow list
+    NotAllowed,
+}
+
+///Sealing hides what a frame says but not its length, and compressing makes the length depend on
+///what the secret has in common with the rest of the frame. Frames with credentials or tokens are
+///never compressed.
+fn carries_secrets(action: &NetworkingAction) -> bool {
+    matches!(
+        action,
+        NetworkingAction::Hello(_) | NetworkingAction::Welcome { .. }
+    )
+}
+
+///Every message is sent as one frame (FramedTcp, a websocket message or a single datagram) of json,
+///compressed if it is over [`COMPRESSION_THRESHOLD`]
+pub fn encode(action: &NetworkingAction) -> Vec<u8> {
+    let json = serde_json::to_vec(action).expect("NetworkingAction is always serializable");
+    if json.len() < COMPRESSION_THRESHOLD || carries_secrets(action) {
+        return json;
+    }
+
+    let mut encoder = DeflateEncoder::new(vec![COMPRESSED], Compression::fast());
+    encoder
+        .write_all(&json)
+        .expect("writing to a vec can't fail");
+    let compressed = encoder.finish().expect("writing to a vec can't fail");
+
+    //payloads that don't compress go as they are
+    if compressed.len() < json.len() {
+        compressed
+    } else {
+        json
+    }
+}
+
+pub fn decode(data: &[u8]) -> Result<NetworkingAction, serde_json::Error> {
+    match data.split_first() {
+        Some((&COMPRESSED, deflated)) => {
+            let inflated = DeflateDecoder::new(deflated).take(MAX_DECOMPRESSED);
+            serde_json::from_reader(inflated)
+        }
+        _ => serde_json::from_slice(data),
+    }
+}
+
+#[test]
+fn compression() {
+    let heartbeat = encode(&NetworkingAction::Heartbeat);
+    assert_eq!(
+        heartbeat,
+        serde_json::to_vec(&NetworkingAction::Heartbeat).unwrap()
+    );
+
+    let rooms = (0..50)
+        .map(|i| RoomInfo {
+            name: format!("room {}", i),
+            players: i,
+            spectators: 0,
+        })
+        .collect();
+    let list = NetworkingAction::RoomList(rooms);
+    let json = serde_json::to_vec(&list).unwrap();
+    let frame = encode(&list);
+    assert_eq!(frame[0], COMPRESSED);
+    assert!(frame.len() < json.len() / 2);
+
+    match decode(&frame) {
+        Ok(NetworkingAction::RoomList(rooms)) => assert_eq!(rooms[49].name, "room 49"),
+        other => panic!("decoded {:?}", other),
+    }
+    //plain json from older clients still decodes
+    assert!(decode(&json).is_ok());
+
+    let hello = NetworkingAction::Hello(Credentials::Secret {
+        name: "a".repeat(1000),
+        secret: "b".repeat(1000),
+    });
+    assert_ne!(encode(&hello)[0], COMPRESSED);
+    assert!(decode(&frame[..frame.len() / 2]).is_err());
+}
@@ -0,0 +1,96 @@
+use bevy::prelude::*;
+
+///Positions are sent as fixed point with this many steps per meter
+pub const POSITION_STEPS: f32 = 512.0;
+
+const QUAT_BITS: u32 = 10;
+const QUAT_MAX: f32 = ((1 << QUAT_BITS) - 1) as f32;
+///The three smallest components of a unit quaternion are always within ±1/sqrt(2)
+const QUAT_RANGE: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+pub fn quantize_f32(v: f32) -> i32 {
+    (v * POSITION_STEPS).round() as i32
+}
+
+pub fn dequantize_f32(v: i32) -> f32 {
+    v as f32 / POSITION_STEPS
+}
+
+pub fn quantize_vec3(v: Vec3) -> [i32; 3] {
+    [quantize_f32(v.x), quantize_f32(v.y), quantize_f32(v.z)]
+}
+
+pub fn dequantize_vec3(v: [i32; 3]) -> Vec3 {
+    Vec3::new(
+        dequantize_f32(v[0]),
+        dequantize_f32(v[1]),
+        dequantize_f32(v[2]),
+    )
+}
+
+///"Smallest three" quaternion compression: the index of the largest component in the top two
+///bits, followed by the other three components at 10 bits each. The largest one is rebuilt
+///from the unit length constraint.
+pub fn compress_quat(q: Quat) -> u32 {
+    let q = q.normalize().to_array();
+
+    let mut largest = 0;
+    for i in 1..4 {
+        if q[i].abs() > q[largest].abs() {
+            largest = i;
+        }
+    }
+
+    //q and -q are the same rotation, so make the dropped component positive
+    let sign = if q[largest] < 0.0 { -1.0 } else { 1.0 };
+
+    let mut packed = largest as u32;
+    for (i, c) in q.iter().enumerate() {
+        if i == largest {
+            continue;
+        }
+        let normalized = (c * sign / QUAT_RANGE + 1.0) * 0.5;
+        let bits = (normalized.clamp(0.0, 1.0) * QUAT_MAX).round() as u32;
+        packed = (packed << QUAT_BITS) | bits;
+    }
+
+    packed
+}
+
+pub fn decompress_quat(packed: u32) -> Quat {
+    let largest = (packed >> (QUAT_BITS * 3)) as usize & 0b11;
+
+    let mut q = [0.0f32; 4];
+    let mut sum = 0.0;
+    let mut shift = QUAT_BITS * 3;
+    for (i, c) in q.iter_mut().enumerate() {
+        if i == largest {
+            continue;
+        }
+        shift -= QUAT_BITS;
+        let bits = (packed >> shift) & ((1 << QUAT_BITS) - 1);
+        *c = (bits as f32 / QUAT_MAX * 2.0 - 1.0) * QUAT_RANGE;
+        sum += *c * *c;
+    }
+    q[largest] = (1.0 - sum).max(0.0).sqrt();
+
+    Quat::from_array(q).normalize()
+}
+
+#[test]
+fn quaternion_roundtrip() {
+    for (yaw, pitch, roll) in [
+        (0.0, 0.0, 0.0),
+        (1.0, 0.0, 0.0),
+        (-2.5, 0.3, 0.0),
+        (3.1, -1.2, 0.7),
+        (0.5, 1.5, -3.0),
+    ] {
+        let q = Quat::from_euler(EulerRot::YXZ, yaw, pitch, roll);
+        let back = decompress_quat(compress_quat(q));
+        assert!(q.angle_between(back) < 0.01, "{:?} became {:?}", q, back);
+    }
+
+    let v = Vec3::new(-999.3, 0.0, 12.345);
+    assert!((dequantize_vec3(quantize_vec3(v)) - v).length() < 1.0 / POSITION_STEPS);
+}
@@ -0,0 +1,257 @@
+use bevy::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::quantize::{compress_quat, decompress_quat, dequantize_vec3, quantize_vec3};
+use crate::snapshot::Snapshot;
+use crate::{enemy::Enemy, Health, Physics, Player};
+
+///Marker for entities the server should send to clients. Only components registered in the
+///[`ReplicationRegistry`] are sent.
+#[derive(Component, Default)]
+pub struct Replicated;
+
+///Id of a replicated entity, the same on the server and on every client
+#[derive(
+    Component, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord,
+)]
+pub struct NetId(pub u64);
+
+///Index of a component in the registry. Client and server build the registry with
+///[`default_registry`] so the indices always agree.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ComponentKind(pub u16);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    Normal,
+    High,
+}
+
+impl Priority {
+    ///Ticks between two checks for changes of a component with this priority
+    pub fn interval(self) -> u64 {
+        match self {
+            Priority::High => 1,
+            Priority::Normal => 3,
+            Priority::Low => 10,
+        }
+    }
+}
+
+///A component that can be sent over the network. `State` is what actually goes over the wire,
+///so it can leave out anything the other side doesn't need.
+pub trait Replicate: Component + Sized {
+    type State: Serialize + DeserializeOwned;
+
+    fn extract(&self) -> Self::State;
+    fn apply(&mut self, state: Self::State);
+    fn from_state(state: Self::State) -> Self;
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ComponentData {
+    pub kind: ComponentKind,
+    pub value: Value,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub enum ReplicationMessage {
+    ///Server to client, sent every tick over the unreliable channel
+    Snapshot(Snapshot),
+    ///Client to server, the newest snapshot the client rebuilt. The server uses it as the
+    ///baseline for the next deltas.
+    Ack { epoch: u64, tick: u64 },
+}
+
+#[derive(Clone, Copy)]
+pub struct RegisteredComponent {
+    pub kind: ComponentKind,
+    pub name: &'static str,
+    pub priority: Priority,
+    extract: fn(&World, Entity) -> Option<Value>,
+    apply: fn(&mut World, Entity, Value),
+}
+
+impl RegisteredComponent {
+    pub fn extract(&self, world: &World, entity: Entity) -> Option<Value> {
+        (self.extract)(world, entity)
+    }
+
+    ///Updates the component on `entity`, inserting it if it doesn't exist yet
+    pub fn apply(&self, world: &mut World, entity: Entity, value: Value) {
+        (self.apply)(world, entity, value)
+    }
+}
+
+///Every component type that gets replicated, in registration order
+#[derive(Clone, Default)]
+pub struct ReplicationRegistry {
+    components: Vec<RegisteredComponent>,
+}
+
+impl ReplicationRegistry {
+    pub fn register<T: Replicate>(&mut self, name: &'static str, priority: Priority) -> &mut Self {
+        let kind = ComponentKind(self.components.len() as u16);
+        self.components.push(RegisteredComponent {
+            kind,
+            name,
+            priority,
+            extract: extract_component::<T>,
+            apply: apply_component::<T>,
+        });
+        self
+    }
+
+    pub fn get(&self, kind: ComponentKind) -> Option<&RegisteredComponent> {
+        self.components.get(kind.0 as usize)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &RegisteredComponent> {
+        self.components.iter()
+    }
+}
+
+fn extract_component<T: Replicate>(world: &World, entity: Entity) -> Option<Value> {
+    let component = world.get::<T>(entity)?;
+    serde_json::to_value(component.extract()).ok()
+}
+
+fn apply_component<T: Replicate>(world: &mut World, entity: Entity, value: Value) {
+    let state: T::State = match serde_json::from_value(value) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("bad {} from server: {}", std::any::type_name::<T>(), e);
+            return;
+        }
+    };
+
+    match world.get_mut::<T>(entity) {
+        Some(mut component) => component.apply(state),
+        None => {
+            world.entity_mut(entity).insert(T::from_state(state));
+        }
+    }
+}
+
+///The registry used by both the client and the server. New replicated components go here.
+pub fn default_registry() -> ReplicationRegistry {
+    let mut registry = ReplicationRegistry::default();
+    registry
+        .register::<Transform>("transform", Priority::High)
+        .register::<Physics>("velocity", Priority::High)
+        .register::<Enemy>("enemy", Priority::Normal)
+        .register::<Health>("health", Priority::Low)
+        .register::<Player>("player", Priority::Low);
+    registry
+}
+
+///Quantized so that snapshot deltas only contain the axes that actually moved
+#[derive(Deserialize, Serialize)]
+pub struct TransformState {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+    pub rotation: u32,
+}
+
+impl Replicate for Transform {
+    type State = TransformState;
+
+    fn extract(&self) -> Self::State {
+        let [x, y, z] = quantize_vec3(self.translation);
+        TransformState {
+            x,
+            y,
+            z,
+            rotation: compress_quat(self.rotation),
+        }
+    }
+
+    fn apply(&mut self, state: Self::State) {
+        self.translation = dequantize_vec3([state.x, state.y, state.z]);
+        self.rotation = decompress_quat(state.rotation);
+    }
+
+    fn from_state(state: Self::State) -> Self {
+        let mut transform = Transform::default();
+        Replicate::apply(&mut transform, state);
+        transform
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct VelocityState {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+///Only the velocity is sent, everything else in [`Physics`] is local simulation state
+impl Replicate for Physics {
+    type State = VelocityState;
+
+    fn extract(&self) -> Self::State {
+        let [x, y, z] = quantize_vec3(self.velocity);
+        VelocityState { x, y, z }
+    }
+
+    fn apply(&mut self, state: Self::State) {
+        self.velocity = dequantize_vec3([state.x, state.y, state.z]);
+    }
+
+    fn from_state(state: Self::State) -> Self {
+        let mut physics = Physics::default();
+        physics.apply(state);
+        physics
+    }
+}
+
+impl Replicate for Enemy {
+    type State = Enemy;
+
+    fn extract(&self) -> Self::State {
+        *self
+    }
+
+    fn apply(&mut self, state: Self::State) {
+        *self = state;
+    }
+
+    fn from_state(state: Self::State) -> Self {
+        state
+    }
+}
+
+impl Replicate for Player {
+    type State = Player;
+
+    fn extract(&self) -> Self::State {
+        *self
+    }
+
+    fn apply(&mut self, state: Self::State) {
+        *self = state;
+    }
+
+    fn from_state(state: Self::State) -> Self {
+        state
+    }
+}
+
+impl Replicate for Health {
+    type State = Health;
+
+    fn extract(&self) -> Self::State {
+        *self
+    }
+
+    fn apply(&mut self, state: Self::State) {
+        *self = state;
+    }
+
+    fn from_state(state: Self::State) -> Self {
+        state
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::replication::{ComponentData, ComponentKind, NetId};
+
+///A snapshot may only use a baseline this many ticks old, anything older gets a full snapshot
+pub const MAX_BASELINE_AGE: u64 = 32;
+
+pub type EntityState = BTreeMap<ComponentKind, Value>;
+
+///Every replicated component of every entity a client can see at one tick
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct WorldState(pub BTreeMap<NetId, EntityState>);
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum ComponentDelta {
+    Full(ComponentData),
+    ///Only the fields of a struct state that differ from the baseline
+    Fields {
+        kind: ComponentKind,
+        fields: Map<String, Value>,
+    },
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct EntityDelta {
+    pub id: NetId,
+    pub components: Vec<ComponentDelta>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    ///The room join this snapshot belongs to, from [`crate::NetworkingAction::RoomJoined`]. A
+    ///late snapshot from a room the client already left has a different one.
+    pub epoch: u64,
+    pub tick: u64,
+    ///The acknowledged tick this snapshot is relative to, `None` for a full snapshot
+    pub baseline: Option<u64>,
+    ///New entities and entities with changed components
+    pub entities: Vec<EntityDelta>,
+    ///Entities in the baseline that are gone now
+    pub removed: Vec<NetId>,
+}
+
+fn component_delta(
+    kind: ComponentKind,
+    old: Option<&Value>,
+    new: &Value,
+) -> Option<ComponentDelta> {
+    match (old, new) {
+        (Some(old), new) if old == new => None,
+        (Some(Value::Object(old)), Value::Object(new))
+            if old.keys().all(|k| new.contains_key(k)) =>
+        {
+            let fields = new
+                .iter()
+                .filter(|(k, v)| old.get(*k) != Some(*v))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            Some(ComponentDelta::Fields { kind, fields })
+        }
+        _ => Some(ComponentDelta::Full(ComponentData {
+            kind,
+            value: new.clone(),
+        })),
+    }
+}
+
+impl WorldState {
+    ///Builds the snapshot that turns `baseline` into `self`
+    pub fn delta(&self, epoch: u64, tick: u64, baseline: Option<(u64, &WorldState)>) -> Snapshot {
+        let empty = WorldState::default();
+        let base = baseline.map(|(_, b)| b).unwrap_or(&empty);
+
+        let entities = self
+            .0
+            .iter()
+            .filter_map(|(id, components)| {
+                let old = base.0.get(id);
+                let components: Vec<ComponentDelta> = components
+                    .iter()
+                    .filter_map(|(kind, value)| {
+                        component_delta(*kind, old.and_then(|o| o.get(kind)), value)
+                    })
+                    .collect();
+
+                if components.is_empty() {
+                    None
+                } else {
+                    Some(EntityDelta {
+                        id: *id,
+                        components,
+                    })
+                }
+            })
+            .collect();
+
+        let removed = base
+            .0
+            .keys()
+            .filter(|id| !self.0.contains_key(id))
+            .copied()
+            .collect();
+
+        Snapshot {
+            epoch,
+            tick,
+            baseline: baseline.map(|(t, _)| t),
+            entities,
+            removed,
+        }
+    }
+
+    ///Rebuilds the full state from a snapshot and the baseline it was made against
+    pub fn apply(baseline: Option<&WorldState>, snapshot: &Snapshot) -> WorldState {
+        let mut state = baseline.cloned().unwrap_or_default();
+
+        for id in snapshot.removed.iter() {
+            state.0.remove(id);
+        }
+
+        for entity in snapshot.entities.iter() {
+            let components = state.0.entry(entity.id).or_default();
+            for delta in entity.components.iter() {
+                match delta {
+                    ComponentDelta::Full(data) => {
+                        components.insert(data.kind, data.value.clone());
+                    }
+                    ComponentDelta::Fields { kind, fields } => {
+                        let value = components
+                            .entry(*kind)
+                            .or_insert_with(|| Value::Object(Map::new()));
+                        if let Value::Object(object) = value {
+                            for (k, v) in fields {
+                                object.insert(k.clone(), v.clone());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        state
+    }
+}
+
+#[test]
+fn delta_roundtrip() {
+    use serde_json::json;
+
+    let (a, b, c) = (NetId(1), NetId(2), NetId(3));
+    let (transform, health) = (ComponentKind(0), ComponentKind(3));
+
+    let mut old = WorldState::default();
+    old.0.insert(
+        a,
+        [(transform, json!({"x": 0, "y": 0, "rotation": 7}))].into(),
+    );
+    old.0
+        .insert(b, [(health, json!({"current": 5.0, "max": 5.0}))].into());
+
+    let mut new = old.clone();
+    new.0.insert(
+        a,
+        [(transform, json!({"x": 12, "y": 0, "rotation": 7}))].into(),
+    );
+    new.0.remove(&b);
+    new.0
+        .insert(c, [(health, json!({"current": 1.0, "max": 5.0}))].into());
+
+    let snapshot = new.delta(1, 10, Some((4, &old)));
+    assert_eq!(snapshot.baseline, Some(4));
+    assert_eq!(snapshot.removed, vec![b]);
+    assert_eq!(
+        snapshot.entities[0].components,
+        vec![ComponentDelta::Fields {
+            kind: transform,
+            fields: [("x".to_string(), json!(12))].into_iter().collect(),
+        }]
+    );
+    assert_eq!(WorldState::apply(Some(&old), &snapshot), new);
+
+    let full = new.delta(1, 10, None);
+    assert_eq!(WorldState::apply(None, &full), new);
+}